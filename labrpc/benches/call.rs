@@ -0,0 +1,112 @@
+//! Per-call overhead of the in-process network.
+//!
+//! A round trip over bare channels between two tasks is the floor that the
+//! overhead of a call adds to. Mean times of `cargo bench -p labrpc --bench
+//! call` in microseconds, one run each:
+//!
+//! | path                                   | channel | sequential | 100 concurrent |
+//! |----------------------------------------|---------|------------|----------------|
+//! | mpsc reply channel, cloned request     | 1.48    | 3.73       | 276            |
+//! | oneshot reply, dispatch without boxing | 1.25    | 3.25       | 203            |
+//! | shared node ids in packages            | 1.25    | 2.37       | 163            |
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use labrpc::{
+    anyhow::Result,
+    futures::future,
+    tokio::{
+        self,
+        runtime::Builder,
+        sync::{mpsc, oneshot},
+        time::Instant,
+    },
+    Network,
+};
+
+labrpc::service! {
+    service echo {
+        fn say(x: u64) -> u64;
+    }
+}
+
+use echo::{Client, Server, Service};
+
+struct Echo;
+
+#[labrpc::async_trait]
+impl Service for Echo {
+    async fn say(&mut self, x: u64) -> Result<u64> {
+        Ok(x)
+    }
+}
+
+/// Spawn an echo node on a running network and return its client.
+async fn echo_client() -> Client {
+    let mut net = Network::new();
+    let (c, server) = net.register_service::<Server<Echo>, Client, _, _>("echo".into(), || Echo);
+    tokio::spawn(server);
+    tokio::spawn(async move { net.run().await });
+    while c.say(0).await.is_err() {}
+    c
+}
+
+/// Spawn a task echoing over bare channels, a hop of a request and a reply
+/// between tasks without labrpc.
+fn echo_channel() -> mpsc::Sender<(u64, oneshot::Sender<u64>)> {
+    let (tx, mut rx) = mpsc::channel::<(u64, oneshot::Sender<u64>)>(100);
+    tokio::spawn(async move {
+        while let Some((x, reply)) = rx.recv().await {
+            let _ = reply.send(x);
+        }
+    });
+    tx
+}
+
+fn bench_call(c: &mut Criterion) {
+    let rt = Builder::new_current_thread().enable_all().build().unwrap();
+    let client = rt.block_on(echo_client());
+    let channel = rt.block_on(async { echo_channel() });
+
+    // Baseline that the overhead of a call is measured against.
+    c.bench_function("sequential channel round trip", |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for i in 0..iters {
+                    let (tx, rx) = oneshot::channel();
+                    channel.send((i, tx)).await.unwrap();
+                    rx.await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+
+    c.bench_function("sequential call", |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for i in 0..iters {
+                    client.say(i).await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+
+    c.bench_function("100 concurrent calls", |b| {
+        b.iter_custom(|iters| {
+            rt.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    future::join_all((0..100).map(|i| client.say(i))).await;
+                }
+                start.elapsed()
+            })
+        })
+    });
+}
+
+criterion_group!(benches, bench_call);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+labrpc::service! {
+    service hello {
+        fn say(a: i32, x: String) -> String;
+    }
+}
+
+use hello::{Client, Server, Service};
+use labrpc::{anyhow::Result, http::Gateway, tokio, Network};
+
+struct MyService {}
+
+#[labrpc::async_trait]
+impl Service for MyService {
+    async fn say(&mut self, a: i32, x: String) -> Result<String> {
+        Ok(x.repeat(a as usize))
+    }
+}
+
+/// Try it with
+///
+/// ```text
+/// curl -d '{"a": 3, "x": "hi"}' http://127.0.0.1:8080/hello/say
+/// ```
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut net = Network::new();
+    let (client, server) =
+        net.register_service::<Server<MyService>, Client, _, _>("hello".to_string(), || {
+            MyService {}
+        });
+    tokio::spawn(server);
+    tokio::spawn(async move { net.run().await });
+
+    Gateway::new()
+        .route(client)
+        .serve("127.0.0.1:8080".parse()?)
+        .await
+}
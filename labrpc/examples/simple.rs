@@ -1,22 +1,54 @@
+use labrpc::serde::{Deserialize, Serialize};
+
+/// Error declared by the service, which is returned to the caller as is.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum HelloError {
+    TooMany(i32),
+}
+
+impl std::fmt::Display for HelloError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HelloError::TooMany(a) => write!(f, "too many repetitions: {}", a),
+        }
+    }
+}
+
 labrpc::service! {
     service hello {
+        type Error = HelloError;
         fn say(a: i32, x: String) -> String;
     }
 }
 
 use hello::{Client, Server, Service};
-use labrpc::anyhow::Result;
+use labrpc::{tokio, Error, Network};
 
-#[derive(Clone)]
 struct MyService {}
 
 #[labrpc::async_trait]
 impl Service for MyService {
-    async fn say(&mut self, a: i32, x: String) -> Result<String> {
-        return Ok(x.to_string());
+    async fn say(&mut self, a: i32, x: String) -> Result<String, Error<HelloError>> {
+        if a > 10 {
+            return Err(Error::Service(HelloError::TooMany(a)));
+        }
+        Ok(x.repeat(a as usize))
     }
 }
 
-fn main() {
-    // let server = Server::new();
+#[tokio::main]
+async fn main() {
+    let mut net = Network::new();
+    let (client, server) =
+        net.register_service::<Server<MyService>, Client, _, _>("hello".to_string(), || {
+            MyService {}
+        });
+    tokio::spawn(server);
+    tokio::spawn(async move { net.run().await });
+
+    match client.say(100, "hi".to_string()).await {
+        Ok(s) => println!("ok: {}", s),
+        Err(Error::Service(e)) => println!("rejected: {}", e),
+        Err(Error::Transport(e)) => println!("failed: {}", e),
+    }
 }
@@ -0,0 +1,297 @@
+//! Batching of calls to the same node.
+//!
+//! A [`Batcher`] sits between clients and a network. Calls from the same
+//! source to the same node issued within a window are sent as one package,
+//! whose data is an envelope of the encoded requests. The
+//! [`Endpoint`](crate::server::Endpoint) of the node unpacks the envelope,
+//! queues each request on its own and replies with the responses of all of
+//! them. Batching is opt-in: clients created with [`Batcher::sender`] batch,
+//! all others do not.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot,
+    },
+    time::Instant,
+};
+
+use crate::{network::NetworkPackage, server::Priority, socket::WireError, trace::TraceContext};
+
+/// Limits of a batch.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// How long the first call of a batch waits for others.
+    pub window: Duration,
+    /// Number of calls that are sent at once without waiting.
+    pub max_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(1),
+            max_size: 64,
+        }
+    }
+}
+
+/// Snapshot of the batches sent by a [`Batcher`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchStats {
+    /// Packages sent, including single calls.
+    pub batches: u64,
+    /// Calls sent.
+    pub calls: u64,
+    /// Largest batch sent.
+    pub max_size: usize,
+}
+
+impl BatchStats {
+    /// Average number of calls per package.
+    pub fn mean_size(&self) -> f64 {
+        if self.batches == 0 {
+            0.0
+        } else {
+            self.calls as f64 / self.batches as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    batches: AtomicU64,
+    calls: AtomicU64,
+    max_size: AtomicUsize,
+}
+
+/// Request of a call in an envelope.
+#[derive(Debug, Serialize, Deserialize)]
+struct Call {
+    data: String,
+    trace: TraceContext,
+    #[serde(default)]
+    compression: bool,
+    #[serde(default)]
+    priority: Priority,
+}
+
+/// Data of a batched package.
+///
+/// The tag is not a valid method name, so it never collides with a request.
+#[derive(Debug, Serialize, Deserialize)]
+enum Envelope {
+    #[serde(rename = "$batch")]
+    Batch(Vec<Call>),
+}
+
+type Responses = Vec<std::result::Result<String, WireError>>;
+
+/// Pending calls and the deadline of their batch by source and destination.
+type Pending = HashMap<(Arc<str>, Arc<str>), (Instant, Vec<NetworkPackage>)>;
+
+/// Coalesces calls to the same node, see the [module](self) documentation.
+#[derive(Debug, Clone)]
+pub struct Batcher {
+    tx: Sender<NetworkPackage>,
+    metrics: Arc<Metrics>,
+}
+
+impl Batcher {
+    /// Batch calls sent through the returned batcher into `net_tx`.
+    pub fn new(net_tx: Sender<NetworkPackage>, config: Config) -> Self {
+        let (tx, rx) = mpsc::channel(100);
+        let metrics = Arc::new(Metrics::default());
+        tokio::spawn(run(rx, net_tx, config, metrics.clone()));
+        Self { tx, metrics }
+    }
+
+    /// Channel to create batching clients with
+    /// [`Client::from_server`](crate::client::Client::from_server).
+    pub fn sender(&self) -> Sender<NetworkPackage> {
+        self.tx.clone()
+    }
+
+    /// Statistics of the batches sent so far.
+    pub fn stats(&self) -> BatchStats {
+        let m = &self.metrics;
+        BatchStats {
+            batches: m.batches.load(Ordering::Relaxed),
+            calls: m.calls.load(Ordering::Relaxed),
+            max_size: m.max_size.load(Ordering::Relaxed),
+        }
+    }
+}
+
+async fn run(
+    mut rx: Receiver<NetworkPackage>,
+    net_tx: Sender<NetworkPackage>,
+    config: Config,
+    metrics: Arc<Metrics>,
+) {
+    let mut pending = Pending::new();
+    loop {
+        let deadline = pending.values().map(|(d, _)| *d).min();
+        tokio::select! {
+            p = rx.recv() => match p {
+                // High-priority calls never wait for others.
+                Some(p) if p.priority == Priority::High => {
+                    send(&net_tx, vec![p], &metrics).await;
+                }
+                Some(p) => {
+                    let key = (p.from.clone(), p.to.clone());
+                    let (_, calls) = pending
+                        .entry(key.clone())
+                        .or_insert_with(|| (Instant::now() + config.window, Vec::new()));
+                    calls.push(p);
+                    if calls.len() >= config.max_size {
+                        let (_, calls) = pending.remove(&key).unwrap();
+                        send(&net_tx, calls, &metrics).await;
+                    }
+                }
+                None => {
+                    for (_, (_, calls)) in pending.drain() {
+                        send(&net_tx, calls, &metrics).await;
+                    }
+                    return;
+                }
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                let now = Instant::now();
+                let due: Vec<_> = pending
+                    .iter()
+                    .filter(|(_, (d, _))| *d <= now)
+                    .map(|(k, _)| k.clone())
+                    .collect();
+                for key in due {
+                    let (_, calls) = pending.remove(&key).unwrap();
+                    send(&net_tx, calls, &metrics).await;
+                }
+            }
+        }
+    }
+}
+
+/// Send calls to the same node as one package.
+async fn send(net_tx: &Sender<NetworkPackage>, mut calls: Vec<NetworkPackage>, metrics: &Metrics) {
+    metrics.batches.fetch_add(1, Ordering::Relaxed);
+    metrics
+        .calls
+        .fetch_add(calls.len() as u64, Ordering::Relaxed);
+    metrics.max_size.fetch_max(calls.len(), Ordering::Relaxed);
+
+    if calls.len() == 1 {
+        let p = calls.pop().unwrap();
+        if net_tx.send(p).await.is_err() {
+            warn!("send to network failed, dropped");
+        }
+        return;
+    }
+
+    let (from, to) = (calls[0].from.clone(), calls[0].to.clone());
+    let mut replies = Vec::with_capacity(calls.len());
+    let mut envelope = Vec::with_capacity(calls.len());
+    for p in calls {
+        replies.push(p.reply);
+        envelope.push(Call {
+            data: p.data,
+            trace: p.trace,
+            compression: p.compression,
+            priority: p.priority,
+        });
+    }
+    let data = serde_json::to_string(&Envelope::Batch(envelope)).expect("envelope is serializable");
+
+    let (reply, rx) = oneshot::channel();
+    let p = NetworkPackage {
+        from,
+        to,
+        reply,
+        data,
+        trace: TraceContext::default(),
+        compression: false,
+        priority: Priority::Normal,
+    };
+    if net_tx.send(p).await.is_err() {
+        warn!("send to network failed, dropped");
+        return;
+    }
+    tokio::spawn(async move {
+        let responses: Responses = match rx.await {
+            Ok(Ok(resp)) => match serde_json::from_str(&resp) {
+                Ok(responses) => responses,
+                Err(e) => vec![Err(anyhow::Error::from(e).into()); replies.len()],
+            },
+            Ok(Err(e)) => vec![Err(e.into()); replies.len()],
+            // Callers see the closed reply channel.
+            Err(_) => return,
+        };
+        for (reply, resp) in replies.into_iter().zip(responses) {
+            let _ = reply.send(resp.map_err(Into::into));
+        }
+    });
+}
+
+/// Unpack a batched package into one package per call.
+///
+/// Returns the package unchanged if it is not batched. Otherwise replies to it
+/// once all calls are answered.
+pub(crate) fn unpack(
+    p: NetworkPackage,
+) -> std::result::Result<Vec<NetworkPackage>, NetworkPackage> {
+    if !p.data.starts_with("{\"$batch\"") {
+        return Err(p);
+    }
+    let calls = match serde_json::from_str(&p.data) {
+        Ok(Envelope::Batch(calls)) => calls,
+        Err(_) => return Err(p),
+    };
+
+    let mut packages = Vec::with_capacity(calls.len());
+    let mut receivers = Vec::with_capacity(calls.len());
+    for Call {
+        data,
+        trace,
+        compression,
+        priority,
+    } in calls
+    {
+        let (reply, rx) = oneshot::channel();
+        packages.push(NetworkPackage {
+            from: p.from.clone(),
+            to: p.to.clone(),
+            reply,
+            data,
+            trace,
+            compression,
+            priority,
+        });
+        receivers.push(rx);
+    }
+
+    let reply = p.reply;
+    tokio::spawn(async move {
+        let mut responses: Responses = Vec::with_capacity(receivers.len());
+        for rx in receivers {
+            responses.push(match rx.await {
+                Ok(resp) => resp.map_err(WireError::from),
+                Err(_) => Err(anyhow!("unable to receive from server").into()),
+            });
+        }
+        let resp = serde_json::to_string(&responses).map_err(Into::into);
+        let _ = reply.send(resp);
+    });
+    Ok(packages)
+}
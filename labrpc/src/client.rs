@@ -1,7 +1,27 @@
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
 use tokio::sync::mpsc::Sender;
 
 use crate::network::NetworkPackage;
 
 pub trait Client {
     fn from_server(server_id: String, net_tx: Sender<NetworkPackage>) -> Self;
+    /// Compress requests of at least `threshold` bytes.
+    fn with_compression(self, threshold: usize) -> Self;
+}
+
+/// Client that issues JSON-encoded requests of a service.
+///
+/// Implemented by every client generated by [`service!`](crate::service).
+#[async_trait::async_trait]
+pub trait Call: Clone + Send + Sync + 'static {
+    /// Name of the service.
+    const SERVICE: &'static str;
+    /// Names of all methods of the service.
+    const METHODS: &'static [&'static str];
+    /// Request enum of the service.
+    type Request: Serialize + DeserializeOwned + Send;
+
+    /// Send an encoded request and wait for the encoded response.
+    async fn call(&self, req: String) -> Result<String>;
 }
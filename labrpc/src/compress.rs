@@ -0,0 +1,56 @@
+//! Compression of large payloads.
+//!
+//! A payload of at least the configured threshold is compressed with LZ4 and
+//! sent as `{"$lz4": "<base64>"}`, which no encoded request or response can be
+//! mistaken for. Compression is negotiated per call: servers decompress
+//! requests whatever their [`Config`](crate::server::Config), and compress a
+//! response only if the caller
+//! [accepts](crate::network::NetworkPackage::compression) it.
+
+use std::convert::TryInto;
+
+use anyhow::{anyhow, Result};
+
+use crate::error::MessageTooLarge;
+
+const PREFIX: &str = "{\"$lz4\":\"";
+const SUFFIX: &str = "\"}";
+
+/// Compress `data` if it has at least `threshold` bytes and gets smaller.
+pub fn encode(data: String, threshold: Option<usize>) -> String {
+    match threshold {
+        Some(threshold) if data.len() >= threshold => {
+            let compressed = lz4_flex::compress_prepend_size(data.as_bytes());
+            let encoded = format!("{}{}{}", PREFIX, base64::encode(compressed), SUFFIX);
+            if encoded.len() < data.len() {
+                encoded
+            } else {
+                data
+            }
+        }
+        _ => data,
+    }
+}
+
+/// Whether `data` was compressed by [`encode`].
+pub fn is_compressed(data: &str) -> bool {
+    data.starts_with(PREFIX)
+}
+
+/// Decompress `data` if it is compressed, into at most `limit` bytes.
+pub fn decode(data: String, limit: usize) -> Result<String> {
+    if !is_compressed(&data) || !data.ends_with(SUFFIX) {
+        return Ok(data);
+    }
+    let compressed = base64::decode(&data[PREFIX.len()..data.len() - SUFFIX.len()])?;
+    if compressed.len() < 4 {
+        return Err(anyhow!("truncated compressed payload"));
+    }
+    let size = u32::from_le_bytes(compressed[..4].try_into().unwrap()) as usize;
+    if size > limit {
+        return Err(MessageTooLarge { size, limit }.into());
+    }
+    let data = lz4_flex::decompress(&compressed[4..], size)
+        .map_err(|e| anyhow!("invalid compressed payload: {:?}", e))?;
+    Ok(String::from_utf8(data)?)
+}
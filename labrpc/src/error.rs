@@ -0,0 +1,123 @@
+//! Errors of calls to services.
+//!
+//! A service may declare its own error type:
+//!
+//! ```ignore
+//! labrpc::service! {
+//!     service acceptor_svc {
+//!         type Error = Rejected;
+//!         fn accept(key: u64, pid: u64) -> ();
+//!     }
+//! }
+//! ```
+//!
+//! Methods of such a service return [`Error<Rejected>`](Error), so that
+//! callers can tell a rejection by the service from a failed call.
+//! Failed calls may be further inspected by downcasting, e.g. to [`Overloaded`].
+
+use std::fmt;
+
+use anyhow::anyhow;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Error of a call to a service with declared error type `E`.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Error returned by the service itself, carried over the wire.
+    Service(E),
+    /// Failure to deliver the request or to receive the response.
+    ///
+    /// Returned by a service, it crashes the server like any untyped error.
+    Transport(anyhow::Error),
+}
+
+impl<E> From<anyhow::Error> for Error<E> {
+    fn from(e: anyhow::Error) -> Self {
+        Error::Transport(e)
+    }
+}
+
+impl<E> From<serde_json::Error> for Error<E> {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Transport(e.into())
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Service(e) => write!(f, "{}", e),
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+/// Error type of the methods generated by [`service!`](crate::service).
+pub trait ServiceError: From<anyhow::Error> + From<serde_json::Error> + Send {
+    /// Encode an error returned by the service as a reply to the caller.
+    ///
+    /// Returns `Err` if the error should crash the server instead.
+    fn into_reply(self) -> anyhow::Result<Value>;
+
+    /// Decode an error replied by the server.
+    fn from_reply(reply: Value) -> Self;
+}
+
+/// Services without declared error type never reply errors.
+impl ServiceError for anyhow::Error {
+    fn into_reply(self) -> anyhow::Result<Value> {
+        Err(self)
+    }
+
+    fn from_reply(reply: Value) -> Self {
+        anyhow!("unexpected error reply: {}", reply)
+    }
+}
+
+impl<E: Serialize + DeserializeOwned + Send> ServiceError for Error<E> {
+    fn into_reply(self) -> anyhow::Result<Value> {
+        match self {
+            Error::Service(e) => Ok(serde_json::to_value(e)?),
+            Error::Transport(e) => Err(e),
+        }
+    }
+
+    fn from_reply(reply: Value) -> Self {
+        match serde_json::from_value(reply) {
+            Ok(e) => Error::Service(e),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// The request was rejected since the queue of the server is full.
+#[derive(Debug, Clone, Copy)]
+pub struct Overloaded;
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "server overloaded")
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+/// The request was rejected since it exceeds the maximum message size.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageTooLarge {
+    /// Size of the request in bytes.
+    pub size: usize,
+    /// Maximum message size of the server.
+    pub limit: usize,
+}
+
+impl fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "message of {} bytes exceeds limit {}", self.size, self.limit)
+    }
+}
+
+impl std::error::Error for MessageTooLarge {}
@@ -0,0 +1,138 @@
+//! Failover across replicas of a service.
+//!
+//! A [`Failover`] holds clients of replicas of the same service and sends a
+//! call to one replica after another, in an order given by its [`Policy`],
+//! until one of them replies successfully or the retry budget is used up.
+//!
+//! ```ignore
+//! let kv = Failover::new(kv_clients).policy(Policy::Sticky);
+//! kv.call(|c| async move { c.get(key.clone()).await }).await?;
+//! ```
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::debug;
+
+/// Order in which replicas are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Start each call at the replica after the one the previous call started at.
+    RoundRobin,
+    /// Start each call at the replica that replied last.
+    Sticky,
+    /// Start each call at the replica set by [`Failover::set_leader`].
+    Leader,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Replica the next round-robin call starts at.
+    next: usize,
+    /// Replica that replied last.
+    last: usize,
+    leader: usize,
+}
+
+/// Clients of replicas of a service, see the [module](self) documentation.
+#[derive(Debug, Clone)]
+pub struct Failover<C> {
+    clients: Vec<C>,
+    policy: Policy,
+    max_attempts: Option<usize>,
+    backoff: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl<C: Clone> Failover<C> {
+    /// Fail over across `clients` round-robin until a call succeeds.
+    pub fn new(clients: Vec<C>) -> Self {
+        assert!(!clients.is_empty(), "no replica to fail over to");
+        Self {
+            clients,
+            policy: Policy::RoundRobin,
+            max_attempts: None,
+            backoff: Duration::default(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Set the order in which replicas are tried.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Give up after `n` failed attempts in total, instead of retrying until a
+    /// call succeeds.
+    pub fn max_attempts(mut self, n: usize) -> Self {
+        self.max_attempts = Some(n);
+        self
+    }
+
+    /// Wait `backoff` after every replica failed once in a row.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Hint the replica that [`Policy::Leader`] tries first.
+    pub fn set_leader(&self, i: usize) {
+        self.state.lock().unwrap().leader = i % self.clients.len();
+    }
+
+    /// Clients of all replicas.
+    pub fn clients(&self) -> &[C] {
+        &self.clients
+    }
+
+    fn start(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        match self.policy {
+            Policy::RoundRobin => {
+                let i = state.next;
+                state.next = (i + 1) % self.clients.len();
+                i
+            }
+            Policy::Sticky => state.last,
+            Policy::Leader => state.leader,
+        }
+    }
+
+    /// Call `f` with the client of one replica after another until it
+    /// succeeds, and return the first successful reply.
+    ///
+    /// Returns the last error if the retry budget is used up.
+    pub async fn call<F, Fut, T, E>(&self, mut f: F) -> Result<T, E>
+    where
+        F: FnMut(C) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: std::fmt::Display,
+    {
+        let n = self.clients.len();
+        let start = self.start();
+        let mut attempts = 0;
+        loop {
+            let i = (start + attempts) % n;
+            match f(self.clients[i].clone()).await {
+                Ok(x) => {
+                    self.state.lock().unwrap().last = i;
+                    return Ok(x);
+                }
+                Err(e) => {
+                    debug!("call to replica {} failed: {}", i, e);
+                    attempts += 1;
+                    if matches!(self.max_attempts, Some(max) if attempts >= max) {
+                        return Err(e);
+                    }
+                }
+            }
+            if attempts % n == 0 && self.backoff > Duration::default() {
+                tokio::time::sleep(self.backoff).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Crashes, partitions and message loss of simulated nodes.
+//!
+//! A crashed node loses its server and with it all volatile state and queued
+//! requests, and comes back with a fresh service created by the factory given
+//! to [`register_service`](crate::Network::register_service) when restarted.
+//! Packages from or to a crashed node, between nodes on different sides of a
+//! partition, or picked by message loss are dropped, which callers see as a
+//! failed call. Loss only applies to requests; responses of delivered
+//! requests always arrive.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use rand::Rng;
+use tokio::sync::watch;
+
+#[derive(Debug, Default)]
+struct Inner {
+    crashed: HashSet<String>,
+    /// Side of the partition by node, nodes without side reach everyone.
+    sides: HashMap<String, usize>,
+    /// Probability to drop a request.
+    loss: f64,
+}
+
+/// Faults of the nodes of a [`Network`](crate::Network), shared by the
+/// network and its handles.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    inner: Arc<Mutex<Inner>>,
+    /// Notified of every change.
+    changed: Arc<watch::Sender<()>>,
+    rx: watch::Receiver<()>,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        let (tx, rx) = watch::channel(());
+        Self {
+            inner: Arc::default(),
+            changed: Arc::new(tx),
+            rx,
+        }
+    }
+}
+
+impl Faults {
+    fn update(&self, f: impl FnOnce(&mut Inner)) {
+        f(&mut self.inner.lock().unwrap());
+        let _ = self.changed.send(());
+    }
+
+    /// Crash node `id`, until it is restarted.
+    pub fn crash(&self, id: &str) {
+        self.update(|x| {
+            x.crashed.insert(id.to_string());
+        });
+    }
+
+    /// Restart the crashed node `id`.
+    pub fn restart(&self, id: &str) {
+        self.update(|x| {
+            x.crashed.remove(id);
+        });
+    }
+
+    /// Whether node `id` is crashed.
+    pub fn is_crashed(&self, id: &str) -> bool {
+        self.inner.lock().unwrap().crashed.contains(id)
+    }
+
+    /// Crashed nodes.
+    pub fn crashed(&self) -> Vec<String> {
+        self.inner.lock().unwrap().crashed.iter().cloned().collect()
+    }
+
+    /// Partition nodes into `sides`, which can only reach nodes of the same
+    /// side and nodes on no side.
+    pub fn partition<S: AsRef<str>>(&self, sides: &[Vec<S>]) {
+        self.update(|x| {
+            x.sides = sides
+                .iter()
+                .enumerate()
+                .flat_map(|(i, side)| side.iter().map(move |id| (id.as_ref().to_string(), i)))
+                .collect();
+        });
+    }
+
+    /// Remove the partition.
+    pub fn heal(&self) {
+        self.update(|x| x.sides.clear());
+    }
+
+    /// Drop requests with probability `loss`.
+    pub fn set_loss(&self, loss: f64) {
+        self.update(|x| x.loss = loss);
+    }
+
+    /// Restart all nodes, heal the partition and stop message loss.
+    pub fn clear(&self) {
+        self.update(|x| *x = Inner::default());
+    }
+
+    /// Whether a request from `from` to `to` is dropped.
+    pub(crate) fn drops(&self, from: &str, to: &str) -> bool {
+        let x = self.inner.lock().unwrap();
+        if x.crashed.contains(from) || x.crashed.contains(to) {
+            return true;
+        }
+        if let (Some(a), Some(b)) = (x.sides.get(from), x.sides.get(to)) {
+            if a != b {
+                return true;
+            }
+        }
+        x.loss > 0.0 && rand::thread_rng().gen_bool(x.loss.min(1.0))
+    }
+
+    /// Wait until node `id` is crashed if `crashed`, running otherwise.
+    pub(crate) async fn wait(&self, id: &str, crashed: bool) {
+        let mut rx = self.rx.clone();
+        while self.is_crashed(id) != crashed {
+            if rx.changed().await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Log of the messages delivered by a [`Network`](crate::Network).
+//!
+//! Recording starts with [`Network::record`](crate::Network::record). A
+//! request is logged once it is queued at its node, so requests dropped by
+//! faults, sent to missing nodes or rejected by the server are not. Each call
+//! of a [batched](crate::batch) package is logged as a message of its own, and
+//! [compressed](crate::compress) requests are logged decompressed, so that
+//! protocol tests can assert invariants over the requests exactly as services
+//! see them.
+//!
+//! ```ignore
+//! let history = net.record();
+//! // ...
+//! let accepts = history.count(|m| m.method() == Some("accept"));
+//! ```
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::time::Instant;
+
+use crate::{compress, network::NetworkPackage};
+
+/// Request delivered to a node.
+#[derive(Debug, Clone)]
+pub struct Message {
+    /// Position in the log.
+    pub seq: usize,
+    /// Source node, or empty if unknown.
+    pub from: String,
+    pub to: String,
+    /// When the request was queued.
+    pub time: Instant,
+    /// Decoded request, `{"<method>": {"<arg>": ...}}`.
+    pub request: Value,
+}
+
+impl Message {
+    /// Name of the requested method.
+    pub fn method(&self) -> Option<&str> {
+        self.request
+            .as_object()
+            .and_then(|x| x.keys().next())
+            .map(String::as_str)
+    }
+
+    /// Argument `name` of the request.
+    pub fn arg(&self, name: &str) -> Option<&Value> {
+        self.request
+            .as_object()
+            .and_then(|x| x.values().next())
+            .and_then(|args| args.get(name))
+    }
+
+    /// Decode the request as the request enum `R` of a service.
+    pub fn decode<R: DeserializeOwned>(&self) -> Option<R> {
+        serde_json::from_value(self.request.clone()).ok()
+    }
+}
+
+/// Shared log of delivered messages.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    enabled: Arc<AtomicBool>,
+    messages: Arc<Mutex<Vec<Message>>>,
+}
+
+impl History {
+    pub(crate) fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Message of the request of a package, or `None` if not recording.
+    pub(crate) fn message(&self, p: &NetworkPackage) -> Option<Message> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let request = compress::decode(p.data.clone(), usize::MAX)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or(Value::Null);
+        Some(Message {
+            seq: 0,
+            from: p.from.to_string(),
+            to: p.to.to_string(),
+            time: Instant::now(),
+            request,
+        })
+    }
+
+    /// Append a message to the log.
+    pub(crate) fn push(&self, mut m: Message) {
+        let mut messages = self.messages.lock().unwrap();
+        m.seq = messages.len();
+        messages.push(m);
+    }
+
+    /// All messages in order of delivery.
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// Messages satisfying `f` in order of delivery.
+    pub fn filter(&self, f: impl Fn(&Message) -> bool) -> Vec<Message> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|m| f(m))
+            .cloned()
+            .collect()
+    }
+
+    /// Number of messages satisfying `f`.
+    pub fn count(&self, f: impl Fn(&Message) -> bool) -> usize {
+        self.messages.lock().unwrap().iter().filter(|m| f(m)).count()
+    }
+
+    /// Forget all messages.
+    pub fn clear(&self) {
+        self.messages.lock().unwrap().clear();
+    }
+}
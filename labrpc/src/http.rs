@@ -0,0 +1,342 @@
+//! HTTP/JSON gateway for services declared by [`service!`](crate::service).
+//!
+//! Every method is exposed as `POST /<service>/<method>`, whose body is a JSON
+//! object of the named arguments, e.g.
+//!
+//! ```text
+//! POST /kv_service/set HTTP/1.1
+//! Content-Length: 45
+//!
+//! {"cmd_id": 1, "key": "hello", "value": "world"}
+//! ```
+//!
+//! The response body is the JSON-encoded return value of the method, or
+//! `{"error": ...}` with status 409 if the service returned its declared error.
+//!
+//! Bodies must be sent with `Content-Length` and are limited to
+//! [`Gateway::max_body_size`]. Requests that cannot be read safely are
+//! answered with an error status, after which the connection is closed.
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use log::{trace, warn};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    client::Call,
+    error::{MessageTooLarge, Overloaded},
+    server::Config,
+};
+
+/// Longest request or header line in bytes.
+const MAX_LINE: usize = 8 << 10;
+/// Most header lines of a request.
+const MAX_HEADERS: usize = 64;
+
+type Handler = Arc<dyn Fn(Value) -> BoxFuture<'static, Result<String>> + Send + Sync>;
+
+struct Route {
+    methods: &'static [&'static str],
+    handler: Handler,
+}
+
+/// HTTP server translating requests to calls of generated clients.
+pub struct Gateway {
+    routes: HashMap<&'static str, Route>,
+    max_body_size: usize,
+}
+
+impl Default for Gateway {
+    fn default() -> Self {
+        Self {
+            routes: HashMap::new(),
+            max_body_size: Config::default().max_message_size,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BadRequest(serde_json::Error);
+
+impl std::fmt::Display for BadRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid arguments: {}", self.0)
+    }
+}
+
+impl std::error::Error for BadRequest {}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+    close: bool,
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, msg: impl ToString) -> Self {
+        Self {
+            status,
+            body: json!({ "error": msg.to_string() }),
+        }
+    }
+}
+
+impl Gateway {
+    /// Create a gateway without any service.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expose all methods of the service behind `client`.
+    pub fn route<C: Call>(mut self, client: C) -> Self {
+        let client = Arc::new(client);
+        let handler: Handler = Arc::new(move |req| {
+            let client = client.clone();
+            Box::pin(async move {
+                // Reject malformed arguments here instead of crashing the server.
+                let req: C::Request = serde_json::from_value(req).map_err(BadRequest)?;
+                client.call(serde_json::to_string(&req)?).await
+            })
+        });
+        self.routes.insert(
+            C::SERVICE,
+            Route {
+                methods: C::METHODS,
+                handler,
+            },
+        );
+        self
+    }
+
+    /// Answer requests with bodies larger than `size` bytes with status 413.
+    ///
+    /// Defaults to the limit of messages of servers by default, see
+    /// [`Config::max_message_size`].
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Listen on `addr` and serve requests until an I/O error occurs.
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        self.listen(TcpListener::bind(addr).await?).await
+    }
+
+    /// Serve requests accepted by `listener` until an I/O error occurs.
+    pub async fn listen(self, listener: TcpListener) -> Result<()> {
+        let max_body_size = self.max_body_size;
+        let routes = Arc::new(self.routes);
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let routes = routes.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_conn(stream, &routes, max_body_size).await {
+                    warn!("http connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn serve_conn(
+    stream: TcpStream,
+    routes: &HashMap<&'static str, Route>,
+    max_body_size: usize,
+) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let (resp, close) = match read_request(&mut stream, max_body_size).await? {
+            None => return Ok(()),
+            Some(Ok(req)) => {
+                trace!("http {} {}", req.method, req.path);
+                let resp = if req.method == "POST" {
+                    dispatch(routes, &req.path, &req.body).await
+                } else {
+                    Response::error(405, "only POST is supported")
+                };
+                (resp, req.close)
+            }
+            // The rest of the request cannot be told apart from the next one.
+            Some(Err(resp)) => (resp, true),
+        };
+
+        let body = resp.body.to_string();
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            resp.status,
+            reason(resp.status),
+            body.len()
+        );
+        let stream = stream.get_mut();
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        if close {
+            stream.shutdown().await?;
+            return Ok(());
+        }
+    }
+}
+
+/// Read a line of at most [`MAX_LINE`] bytes, `None` if it is longer.
+async fn read_line(stream: &mut BufReader<TcpStream>) -> Result<Option<String>> {
+    let mut line = String::new();
+    (&mut *stream)
+        .take(MAX_LINE as u64)
+        .read_line(&mut line)
+        .await?;
+    if line.len() == MAX_LINE && !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+/// Read the next request, or the response refusing it.
+///
+/// Returns `None` once the peer closed the connection.
+async fn read_request(
+    stream: &mut BufReader<TcpStream>,
+    max_body_size: usize,
+) -> Result<Option<Result<Request, Response>>> {
+    let line = match read_line(stream).await? {
+        Some(line) if line.is_empty() => return Ok(None),
+        Some(line) => line,
+        None => return Ok(Some(Err(Response::error(414, "request line too long")))),
+    };
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/") => {
+            (method.to_string(), path.to_string())
+        }
+        _ => {
+            let e = format!("malformed request line: {:?}", line.trim_end());
+            return Ok(Some(Err(Response::error(400, e))));
+        }
+    };
+
+    let mut content_length = 0;
+    let mut close = false;
+    let mut headers = 0;
+    loop {
+        let header = match read_line(stream).await? {
+            Some(header) if header.is_empty() => return Err(anyhow!("unexpected end of headers")),
+            Some(header) => header,
+            None => return Ok(Some(Err(Response::error(431, "header line too long")))),
+        };
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Ok(Some(Err(Response::error(431, "too many headers"))));
+        }
+        let mut kv = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (kv.next(), kv.next()) {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => match value.parse() {
+                    Ok(len) => content_length = len,
+                    Err(_) => {
+                        let e = format!("invalid content length {:?}", value);
+                        return Ok(Some(Err(Response::error(400, e))));
+                    }
+                },
+                "transfer-encoding" => {
+                    let e = "transfer encodings are not supported, send a content length";
+                    return Ok(Some(Err(Response::error(501, e))));
+                }
+                "connection" => close = value.eq_ignore_ascii_case("close"),
+                _ => {}
+            }
+        }
+    }
+
+    if content_length > max_body_size {
+        let e = MessageTooLarge {
+            size: content_length,
+            limit: max_body_size,
+        };
+        return Ok(Some(Err(Response::error(413, e))));
+    }
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await?;
+    Ok(Some(Ok(Request {
+        method,
+        path,
+        body,
+        close,
+    })))
+}
+
+async fn dispatch(routes: &HashMap<&'static str, Route>, path: &str, body: &[u8]) -> Response {
+    let mut segs = path.trim_matches('/').split('/');
+    let (service, method) = match (segs.next(), segs.next(), segs.next()) {
+        (Some(service), Some(method), None) => (service, method),
+        _ => return Response::error(404, "expected /<service>/<method>"),
+    };
+    let route = match routes.get(service) {
+        Some(route) if route.methods.contains(&method) => route,
+        _ => return Response::error(404, format!("unknown method {}/{}", service, method)),
+    };
+
+    let args: Value = if body.is_empty() {
+        json!({})
+    } else {
+        match serde_json::from_slice(body) {
+            Ok(args) => args,
+            Err(e) => return Response::error(400, e),
+        }
+    };
+    // Requests are externally tagged enums: `{"<method>": {<args>}}`.
+    let req = json!({ method: args });
+
+    match (route.handler)(req).await {
+        Ok(resp) => match serde_json::from_str::<Value>(&resp) {
+            Ok(mut resp) => match resp.get_mut("error") {
+                // Error declared by the service.
+                Some(e) => Response {
+                    status: 409,
+                    body: json!({ "error": e.take() }),
+                },
+                None => Response::ok(resp["data"].take()),
+            },
+            Err(e) => Response::error(502, e),
+        },
+        Err(e) if e.is::<BadRequest>() => Response::error(400, e),
+        Err(e) if e.is::<MessageTooLarge>() => Response::error(413, e),
+        Err(e) if e.is::<Overloaded>() => Response::error(503, e),
+        Err(e) => Response::error(502, e),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Bad Gateway",
+    }
+}
@@ -1,10 +1,23 @@
 #![feature(async_closure)]
 #![feature(type_alias_impl_trait)]
 
+pub mod batch;
 pub mod client;
+pub mod compress;
+pub mod error;
+pub mod failover;
+pub mod fault;
+pub mod history;
+pub mod http;
+pub mod link;
 mod macros;
+pub mod nemesis;
 pub mod network;
 pub mod server;
+pub mod socket;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod trace;
 
 pub use anyhow;
 pub use async_trait::async_trait;
@@ -14,7 +27,9 @@ pub use rand;
 pub use serde;
 pub use serde_json;
 pub use tokio;
+pub use tracing;
 
+pub use error::Error;
 pub use network::Network;
 // pub use labrpc_macro::server;
 // pub use labrpc_macro::service;
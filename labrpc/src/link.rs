@@ -0,0 +1,134 @@
+//! Latency and bandwidth model of simulated links.
+//!
+//! A link is directed: a request from `a` to `b` is delayed by link `a -> b`,
+//! and its response by link `b -> a`. Packages sent by clients without a
+//! [source](crate::network::NetworkPackage::from) are only delayed by the
+//! default link.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rand::Rng;
+use tokio::time::Instant;
+
+/// Model of a one-way link.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Link {
+    /// Propagation delay.
+    pub latency: Duration,
+    /// Upper bound of the extra delay, which is uniformly distributed.
+    pub jitter: Duration,
+    /// Bytes per second, unlimited if `None`.
+    ///
+    /// Packages on the same link are transmitted one after another.
+    pub bandwidth: Option<u64>,
+}
+
+impl Link {
+    /// Link with fixed latency and unlimited bandwidth.
+    pub fn new(latency: Duration) -> Self {
+        Self {
+            latency,
+            ..Self::default()
+        }
+    }
+
+    /// Set the upper bound of the extra delay.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the bandwidth in bytes per second.
+    pub fn bandwidth(mut self, bandwidth: u64) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    fn is_instant(&self) -> bool {
+        self.latency == Duration::default()
+            && self.jitter == Duration::default()
+            && self.bandwidth.is_none()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    default: Link,
+    links: HashMap<(String, String), Link>,
+    /// When the link becomes free for the next package.
+    busy_until: HashMap<(String, String), Instant>,
+}
+
+/// Table of links between nodes, shared by a [`Network`](crate::Network)
+/// and its handles.
+#[derive(Debug, Clone, Default)]
+pub struct Links {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Links {
+    /// Set the link used between nodes without specific link.
+    pub fn set_default(&self, link: Link) {
+        self.inner.lock().unwrap().default = link;
+    }
+
+    /// Set the link from node `from` to node `to`.
+    pub fn set(&self, from: &str, to: &str, link: Link) {
+        let key = (from.to_string(), to.to_string());
+        self.inner.lock().unwrap().links.insert(key, link);
+    }
+
+    /// Set the links in both directions between nodes `a` and `b`.
+    pub fn connect(&self, a: &str, b: &str, link: Link) {
+        self.set(a, b, link);
+        self.set(b, a, link);
+    }
+
+    /// Connect every pair of nodes in different regions by `link`.
+    pub fn set_regions<S: AsRef<str>>(&self, regions: &[Vec<S>], link: Link) {
+        for (i, r) in regions.iter().enumerate() {
+            for other in regions.iter().skip(i + 1) {
+                for a in r {
+                    for b in other {
+                        self.connect(a.as_ref(), b.as_ref(), link);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether packages from `from` to `to` are delayed at all.
+    pub(crate) fn is_instant(&self, from: &str, to: &str) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .links
+            .get(&(from.to_string(), to.to_string()))
+            .unwrap_or(&inner.default)
+            .is_instant()
+    }
+
+    /// Delay of a package of `size` bytes sent from `from` to `to` now.
+    pub(crate) fn delay(&self, from: &str, to: &str, size: usize) -> Duration {
+        let mut inner = self.inner.lock().unwrap();
+        let key = (from.to_string(), to.to_string());
+        let link = *inner.links.get(&key).unwrap_or(&inner.default);
+        let now = Instant::now();
+
+        let mut arrival = now;
+        if let Some(bw) = link.bandwidth {
+            let busy_until = inner.busy_until.entry(key).or_insert(now);
+            let start = (*busy_until).max(now);
+            *busy_until = start + Duration::from_secs_f64(size as f64 / bw as f64);
+            arrival = *busy_until;
+        }
+        arrival += link.latency;
+        if link.jitter > Duration::default() {
+            arrival += rand::thread_rng().gen_range(Duration::default()..link.jitter);
+        }
+        arrival - now
+    }
+}
@@ -8,12 +8,40 @@ macro_rules! random_error {
             let x: f32 = rng.gen_range(0.0..1.0);
             if x < $prob {
                 $crate::log::error!("random error");
-                return Err($crate::anyhow::anyhow!("random error"));
+                return Err($crate::anyhow::anyhow!("random error").into());
             }
         }
     };
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __service_error {
+    () => {
+        $crate::anyhow::Error
+    };
+    ($err_ty:ty) => {
+        $crate::error::Error<$err_ty>
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __service_priority {
+    () => {
+        $crate::server::Priority::Normal
+    };
+    (normal) => {
+        $crate::server::Priority::Normal
+    };
+    (high) => {
+        $crate::server::Priority::High
+    };
+    ($other:ident) => {
+        compile_error!(concat!("unknown priority ", stringify!($other)))
+    };
+}
+
 #[macro_export]
 macro_rules! service {
     () => {
@@ -22,8 +50,10 @@ macro_rules! service {
     (
         $(#[$service_attr:meta])*
         service $svc_name:ident {
+            $(type Error = $err_ty:ty;)?
             $(
                 $(#[$method_attr:meta])*
+                $(priority($priority:ident))?
                 fn $method_name:ident($($arg_id:ident: $arg_ty:ty),*) -> $output:ty;
             )*
         }
@@ -34,16 +64,21 @@ macro_rules! service {
             use super::*;
 
             use $crate::network::{Network, NetworkPackage};
-            use $crate::{server, client};
+            use $crate::{server, client, compress, socket, error::ServiceError};
+            use $crate::server::{Config, Endpoint, Mailbox, Priority};
 
-            use $crate::tokio::sync::mpsc::{self, Sender, Receiver};
+            use $crate::tokio::sync::{mpsc::Sender, oneshot};
             use $crate::serde_json::{self, Value};
             use $crate::serde::{Serialize, Deserialize};
             use $crate::anyhow::{Result, anyhow};
             use $crate::async_trait;
             use $crate::log::{error, trace};
+            use $crate::trace::TraceContext;
+            use $crate::tracing::{self, Instrument};
 
 
+            type __Error = $crate::__service_error!($($err_ty)?);
+
             #[derive(Debug, Deserialize, Serialize)]
             pub enum Request {
                 $(
@@ -52,14 +87,30 @@ macro_rules! service {
                 ),*
             }
 
+            impl Request {
+                /// Name of the requested method.
+                pub fn method(&self) -> &'static str {
+                    match self {
+                        $(Request::$method_name { .. } => stringify!($method_name)),*
+                    }
+                }
+
+                /// Lane of the request at the server.
+                pub fn priority(&self) -> Priority {
+                    match self {
+                        $(Request::$method_name { .. } => $crate::__service_priority!($($priority)?)),*
+                    }
+                }
+            }
+
             mod response {
                 use super::*;
                 $(
                     #[derive(Deserialize, Serialize)]
                     #[allow(non_camel_case_types)]
-                    pub struct $method_name {
-                        // TODO: status field
-                        pub data: $output
+                    pub enum $method_name {
+                        data($output),
+                        error(Value),
                     }
                 )*
             }
@@ -68,37 +119,82 @@ macro_rules! service {
             pub trait Service: Send + 'static {
                 $(
                     $(#[$method_attr])*
-                    async fn $method_name(&mut self, $($arg_id : $arg_ty),* ) -> Result<$output>;
+                    async fn $method_name(&mut self, $($arg_id : $arg_ty),* ) -> Result<$output, __Error>;
                 )*
+
+                /// Future the reply to the request just handled waits for,
+                /// without holding up the requests after it. An error is
+                /// replied instead of the response.
+                fn reply_after(&mut self) -> Option<$crate::futures::future::BoxFuture<'static, Result<()>>> {
+                    None
+                }
             }
 
             #[derive(Debug, Clone)]
             pub struct Client {
-                server_id: String,
+                server_id: ::std::sync::Arc<str>,
+                from: ::std::sync::Arc<str>,
                 tx: Sender<NetworkPackage>,
+                compress_threshold: Option<usize>,
             }
 
             impl Client {
+                /// Mark requests of this client as sent by node `from`.
+                pub fn with_source(mut self, from: impl Into<String>) -> Self {
+                    self.from = from.into().into();
+                    self
+                }
 
                 $(
-                    pub async fn $method_name(&self, $($arg_id : $arg_ty),* ) -> Result<$output> {
-                        let req = Request::$method_name {
-                            $($arg_id),*
-                        };
-                        let resp = self.call(serde_json::to_string(&req)?).await?;
-                        let resp: response::$method_name = $crate::serde_json::from_str(&resp)?;
-                        Ok(resp.data)
+                    pub async fn $method_name(&self, $($arg_id : $arg_ty),* ) -> Result<$output, __Error> {
+                        let span = tracing::info_span!(
+                            "call",
+                            service = stringify!($svc_name),
+                            method = stringify!($method_name),
+                            to = %self.server_id,
+                            call_id = tracing::field::Empty,
+                        );
+                        async move {
+                            let req = Request::$method_name {
+                                $($arg_id),*
+                            };
+                            let priority = req.priority();
+                            let resp = self.call_with_priority(serde_json::to_string(&req)?, priority).await?;
+                            match serde_json::from_str::<response::$method_name>(&resp)? {
+                                response::$method_name::data(data) => Ok(data),
+                                response::$method_name::error(e) => Err(ServiceError::from_reply(e)),
+                            }
+                        }
+                        .instrument(span)
+                        .await
                     }
                 )*
 
                 pub async fn call(&self, req: String) -> Result<String> {
-                    let (tx, mut rx) = mpsc::channel(100);
-                    self.tx.send(NetworkPackage{to: self.server_id.clone(), reply: tx, data: req.clone()}).await?;
-                    if let Some(resp) = rx.recv().await {
-                        trace!("req: {}, resp: {}", req, &resp);
-                        Ok(resp)
-                    } else {
-                        Err(anyhow!("unable to receive from server"))
+                    self.call_with_priority(req, Priority::Normal).await
+                }
+
+                /// Send an encoded request in the lane of `priority`.
+                pub async fn call_with_priority(&self, req: String, priority: Priority) -> Result<String> {
+                    trace!("req: {}", &req);
+                    let (tx, rx) = oneshot::channel();
+                    self.tx.send(NetworkPackage{
+                        from: self.from.clone(),
+                        to: self.server_id.clone(),
+                        reply: tx,
+                        data: compress::encode(req, self.compress_threshold),
+                        trace: TraceContext::current(),
+                        compression: true,
+                        priority,
+                    }).await?;
+                    match rx.await {
+                        Ok(Ok(resp)) => {
+                            let resp = compress::decode(resp, socket::MAX_FRAME_SIZE)?;
+                            trace!("resp: {}", &resp);
+                            Ok(resp)
+                        }
+                        Ok(Err(e)) => Err(e),
+                        Err(_) => Err(anyhow!("unable to receive from server")),
                     }
                 }
             }
@@ -106,54 +202,105 @@ macro_rules! service {
             impl client::Client for Client {
                 fn from_server(server_id: String, net_tx: Sender<NetworkPackage>) -> Self {
                     Self {
-                        server_id,
+                        server_id: server_id.into(),
+                        from: "".into(),
                         tx: net_tx,
+                        compress_threshold: None,
                     }
                 }
+
+                fn with_compression(mut self, threshold: usize) -> Self {
+                    self.compress_threshold = Some(threshold);
+                    self
+                }
+            }
+
+            #[async_trait]
+            impl client::Call for Client {
+                const SERVICE: &'static str = stringify!($svc_name);
+                const METHODS: &'static [&'static str] = &[$(stringify!($method_name)),*];
+                type Request = Request;
+
+                async fn call(&self, req: String) -> Result<String> {
+                    Client::call(self, req).await
+                }
             }
 
             #[derive(Debug)]
             pub struct Server<T: Service + Send> {
                 svc: T,
-                tx: Sender<NetworkPackage>,
-                rx: Receiver<NetworkPackage>,
+                endpoint: Endpoint,
+                mailbox: Mailbox,
             }
 
             #[async_trait]
             impl<T: Service + Send> server::Server for Server<T> {
                 type Service = T;
 
-                fn from_service(svc: Self::Service) -> Self {
-                    let (tx, rx) = mpsc::channel(100);
-                    Self {svc, tx, rx}
+                fn with_config(svc: Self::Service, config: Config) -> Self {
+                    let (endpoint, mailbox) = server::channel(config);
+                    Self {svc, endpoint, mailbox}
                 }
 
-                fn client_chan(&self) -> Sender<NetworkPackage> {
-                    return self.tx.clone();
+                fn endpoint(&self) -> Endpoint {
+                    self.endpoint.clone()
                 }
 
                 async fn handle(&mut self) -> Result<()> {
+                    match self.mailbox.recv().await {
+                        Some(p) => self.dispatch(p).await,
+                        None => Err(anyhow!("expected sender")),
+                    }
+                }
+
+                async fn run(&mut self) -> Result<()> {
+                    // Dispatch without boxing a future per request.
+                    while let Some(p) = self.mailbox.recv().await {
+                        self.dispatch(p).await?;
+                    }
+                    Err(anyhow!("expected sender"))
+                }
+            }
 
-                    match self.rx.recv().await {
-                        Some(NetworkPackage{to, reply, data}) => {
+            impl<T: Service + Send> Server<T> {
+                async fn dispatch(&mut self, p: NetworkPackage) -> Result<()> {
+                    match p {
+                        NetworkPackage{to, reply, data, trace, compression, ..} => {
                             trace!("handle recv: {}", &data);
                             let req: Request = serde_json::from_str(&data)?;
+                            let span = tracing::info_span!(
+                                "handle",
+                                service = stringify!($svc_name),
+                                method = req.method(),
+                                node = %to,
+                                caller = trace.caller(),
+                            );
                             match req {
                                 $(
                                     Request::$method_name { $($arg_id),* } => {
-                                        let data = self.svc.$method_name($($arg_id),* ).await?;
-                                        let resp = response::$method_name {
-                                            data
+                                        let resp = match self.svc.$method_name($($arg_id),* ).instrument(span).await {
+                                            Ok(data) => response::$method_name::data(data),
+                                            Err(e) => response::$method_name::error(e.into_reply()?),
                                         };
                                         let resp = serde_json::to_string(&resp)?;
                                         trace!("handle send: {}", &resp);
-                                        reply.send(resp).await?;
+                                        let resp = self.mailbox.encode_response(resp, compression);
+                                        // The caller may have given up.
+                                        match self.svc.reply_after() {
+                                            Some(pending) => {
+                                                $crate::tokio::spawn(async move {
+                                                    let _ = reply.send(pending.await.map(|()| resp));
+                                                });
+                                            }
+                                            None => {
+                                                let _ = reply.send(Ok(resp));
+                                            }
+                                        }
                                         Ok(())
                                     }
                                 )*
                             }
                         }
-                        None => {Err(anyhow!("expected sender"))}
                     }
                 }
             }
@@ -0,0 +1,209 @@
+//! Randomized fault injection for long-running cluster tests.
+//!
+//! A [`Nemesis`] repeatedly crashes and restarts nodes, partitions and heals
+//! the network and toggles message loss through the [`Faults`] of one or more
+//! networks. Its schedule is fully determined by a seed, which
+//! [`seed_from_env`] takes from [`SEED_ENV`] so that a failed run can be
+//! reproduced, and every action is logged.
+
+use std::{fmt, time::Duration};
+
+use log::info;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::fault::Faults;
+
+/// Environment variable of the seed, see [`seed_from_env`].
+pub const SEED_ENV: &str = "LABRPC_SEED";
+
+/// Seed from [`SEED_ENV`], or a random one.
+pub fn seed_from_env() -> u64 {
+    std::env::var(SEED_ENV)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| rand::thread_rng().gen())
+}
+
+/// Prints the seed if dropped while panicking, e.g. when a test fails.
+#[derive(Debug)]
+pub struct SeedGuard(pub u64);
+
+impl Drop for SeedGuard {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            eprintln!(
+                "nemesis seed: {}, rerun with {}={}",
+                self.0, SEED_ENV, self.0
+            );
+        }
+    }
+}
+
+/// Limits of the faults injected by a [`Nemesis`].
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Time between two actions.
+    pub interval: Duration,
+    /// Number of nodes crashed at the same time.
+    pub max_crashed: usize,
+    /// Largest probability of message loss.
+    pub max_loss: f64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(500),
+            max_crashed: 1,
+            max_loss: 0.1,
+        }
+    }
+}
+
+/// Fault injected by a [`Nemesis`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Crash a node.
+    Crash(String),
+    /// Restart a crashed node.
+    Restart(String),
+    /// Split the nodes into sides.
+    Partition(Vec<Vec<String>>),
+    /// Heal the partition and stop message loss.
+    Heal,
+    /// Drop requests with the given probability.
+    Loss(f64),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Crash(id) => write!(f, "crash {}", id),
+            Action::Restart(id) => write!(f, "restart {}", id),
+            Action::Partition(sides) => write!(f, "partition {:?}", sides),
+            Action::Heal => write!(f, "heal"),
+            Action::Loss(p) => write!(f, "loss {:.3}", p),
+        }
+    }
+}
+
+/// Injects random faults, see the [module](self) documentation.
+pub struct Nemesis {
+    seed: u64,
+    rng: StdRng,
+    config: Config,
+    /// Networks and the nodes on them that may crash.
+    targets: Vec<(Faults, Vec<String>)>,
+    history: Vec<Action>,
+}
+
+impl Nemesis {
+    /// Create a nemesis whose schedule is determined by `seed`.
+    pub fn new(seed: u64, config: Config) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            config,
+            targets: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Inject faults into `nodes` through `faults`.
+    ///
+    /// Partitions and message loss apply to all networks added, partitions
+    /// split the nodes of all of them.
+    pub fn target<S: Into<String>>(mut self, faults: Faults, nodes: Vec<S>) -> Self {
+        let nodes = nodes.into_iter().map(Into::into).collect();
+        self.targets.push((faults, nodes));
+        self
+    }
+
+    /// Seed of the schedule.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Actions taken so far.
+    pub fn history(&self) -> &[Action] {
+        &self.history
+    }
+
+    /// Take one random action.
+    pub fn step(&mut self) -> Action {
+        let mut crashed: Vec<(usize, String)> = self
+            .targets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (faults, _))| faults.crashed().into_iter().map(move |id| (i, id)))
+            .collect();
+        // Keep the schedule independent of the order of the crashed set.
+        crashed.sort();
+        let running: Vec<(usize, String)> = self
+            .targets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (faults, nodes))| {
+                nodes
+                    .iter()
+                    .filter(move |id| !faults.is_crashed(id))
+                    .map(move |id| (i, id.clone()))
+            })
+            .collect();
+
+        let action = match self.rng.gen_range(0..5) {
+            0 if crashed.len() < self.config.max_crashed && !running.is_empty() => {
+                let (i, id) = running.choose(&mut self.rng).unwrap().clone();
+                self.targets[i].0.crash(&id);
+                Action::Crash(id)
+            }
+            0 | 1 if !crashed.is_empty() => {
+                let (i, id) = crashed.choose(&mut self.rng).unwrap().clone();
+                self.targets[i].0.restart(&id);
+                Action::Restart(id)
+            }
+            2 => {
+                let mut sides = vec![Vec::new(), Vec::new()];
+                for (_, nodes) in self.targets.iter() {
+                    for id in nodes {
+                        sides[self.rng.gen_range(0..2)].push(id.clone());
+                    }
+                }
+                for (faults, _) in self.targets.iter() {
+                    faults.partition(&sides);
+                }
+                Action::Partition(sides)
+            }
+            3 => {
+                let loss = self.rng.gen_range(0.0..=self.config.max_loss);
+                for (faults, _) in self.targets.iter() {
+                    faults.set_loss(loss);
+                }
+                Action::Loss(loss)
+            }
+            _ => {
+                for (faults, _) in self.targets.iter() {
+                    faults.heal();
+                    faults.set_loss(0.0);
+                }
+                Action::Heal
+            }
+        };
+        info!("nemesis: {}", action);
+        self.history.push(action.clone());
+        action
+    }
+
+    /// Take an action every interval for `duration`, then clear all faults.
+    pub async fn run(&mut self, duration: Duration) {
+        let deadline = tokio::time::Instant::now() + duration;
+        while tokio::time::Instant::now() < deadline {
+            self.step();
+            tokio::time::sleep(self.config.interval).await;
+        }
+        for (faults, _) in self.targets.iter() {
+            faults.clear();
+        }
+        info!("nemesis: clear");
+    }
+}
@@ -1,28 +1,52 @@
 use std::{
     collections::HashMap,
+    mem,
     sync::{Arc, Mutex},
 };
 
 use anyhow::Result;
 use futures::Future;
-use log::{info, warn};
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use log::{debug, info, warn};
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    oneshot,
+};
 
-use crate::{client::Client, server::Server};
+use crate::{
+    client::Client,
+    fault::Faults,
+    history::History,
+    link::Links,
+    server::{Config, Endpoint, Priority, QueueStats, Server},
+    trace::TraceContext,
+};
 
 pub fn is_send<T: Send>(x: &T) {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct NetworkPackage {
-    pub to: String,
-    pub reply: Sender<String>,
+    /// Source node, or empty if unknown.
+    pub from: Arc<str>,
+    pub to: Arc<str>,
+    pub reply: oneshot::Sender<Result<String>>,
     pub data: String,
+    pub trace: TraceContext,
+    /// Whether the sender accepts a [compressed](crate::compress) response.
+    pub compression: bool,
+    /// Lane the request is queued in.
+    pub priority: Priority,
 }
 
+/// Endpoints of the nodes of a network by id.
+pub type Nodes = Arc<Mutex<HashMap<String, Endpoint>>>;
+
 pub struct Network {
     pub tx: Sender<NetworkPackage>,
     rx: Receiver<NetworkPackage>,
-    pub nodes: Arc<Mutex<HashMap<String, Sender<NetworkPackage>>>>,
+    pub nodes: Nodes,
+    links: Links,
+    faults: Faults,
+    history: History,
 }
 
 impl Network {
@@ -32,33 +56,97 @@ impl Network {
             tx,
             rx,
             nodes: Arc::new(Mutex::new(HashMap::default())),
+            links: Links::default(),
+            faults: Faults::default(),
+            history: History::default(),
         }
     }
 
+    /// Links between nodes, which can be modified while the network runs.
+    pub fn links(&self) -> Links {
+        self.links.clone()
+    }
+
+    /// Faults of nodes, which can be injected while the network runs.
+    pub fn faults(&self) -> Faults {
+        self.faults.clone()
+    }
+
+    /// Start logging delivered messages and return the log.
+    pub fn record(&self) -> History {
+        self.history.enable();
+        self.history.clone()
+    }
+
     pub fn register_service<S, C, F, V>(&self, id: String, f: F) -> (C, impl Future<Output = ()>)
     where
         F: Fn() -> V,
         S: Server<Service = V> + Send + 'static,
         C: Client,
     {
-        let acc_client = C::from_server(id.clone(), self.tx.clone());
+        self.register_service_with_config::<S, C, F, V>(id, Config::default(), f)
+    }
+
+    /// Register a service whose server is limited by `config`.
+    pub fn register_service_with_config<S, C, F, V>(
+        &self,
+        id: String,
+        config: Config,
+        f: F,
+    ) -> (C, impl Future<Output = ()>)
+    where
+        F: Fn() -> V,
+        S: Server<Service = V> + Send + 'static,
+        C: Client,
+    {
+        let mut acc_client = C::from_server(id.clone(), self.tx.clone());
+        if let Some(threshold) = config.compress_threshold {
+            acc_client = acc_client.with_compression(threshold);
+        }
         let nodes = self.nodes.clone();
+        let faults = self.faults.clone();
+        // Register at once, so that requests sent before the server runs are queued.
+        let mut first = Some(S::with_config(f(), config));
+        nodes
+            .lock()
+            .unwrap()
+            .insert(id.clone(), first.as_ref().unwrap().endpoint());
         (acc_client, async move {
             loop {
-                let mut server = S::from_service(f());
-                nodes
-                    .lock()
-                    .unwrap()
-                    .insert(id.clone(), server.client_chan());
-                if let Ok(_) = server.run().await {
-                    break;
-                } else {
-                    info!("server restart");
+                faults.wait(&id, false).await;
+                let mut server = match first.take() {
+                    Some(server) => server,
+                    None => {
+                        let server = S::with_config(f(), config);
+                        nodes
+                            .lock()
+                            .unwrap()
+                            .insert(id.clone(), server.endpoint());
+                        server
+                    }
+                };
+                tokio::select! {
+                    res = server.run() => {
+                        if let Ok(_) = res {
+                            break;
+                        } else {
+                            info!("server restart");
+                        }
+                    }
+                    _ = faults.wait(&id, true) => {
+                        nodes.lock().unwrap().remove(&id);
+                        info!("server {} crashed", id);
+                    }
                 }
             }
         })
     }
 
+    /// Statistics of the request queue of node `id`.
+    pub fn queue_stats(&self, id: &str) -> Option<QueueStats> {
+        self.nodes.lock().unwrap().get(id).map(|x| x.stats())
+    }
+
     pub async fn run(&mut self) {
         loop {
             let p = self
@@ -66,18 +154,48 @@ impl Network {
                 .recv()
                 .await
                 .expect("sender cannot be dropped by itself");
-            let node = {
-                let x = self.nodes.lock().unwrap();
-                x.get(&p.to).map(|x| x.clone())
-            };
-
-            if let Some(x) = node {
-                if x.send(p).await.is_err() {
-                    warn!("send to node failed, dropped");
-                }
-            } else {
-                warn!("node not found");
+            if self.faults.drops(&p.from, &p.to) {
+                debug!("drop package from {:?} to {}", p.from, p.to);
+                continue;
+            }
+            if self.links.is_instant(&p.from, &p.to) && self.links.is_instant(&p.to, &p.from) {
+                deliver(&self.nodes, p, Some(&self.history));
+                continue;
             }
+
+            // Delay in a separate task, which must not hold up other packages.
+            let nodes = self.nodes.clone();
+            let links = self.links.clone();
+            let history = self.history.clone();
+            tokio::spawn(async move {
+                let mut p = p;
+                let (from, to) = (p.from.clone(), p.to.clone());
+                tokio::time::sleep(links.delay(&from, &to, p.data.len())).await;
+
+                let (tx, rx) = oneshot::channel();
+                let reply = mem::replace(&mut p.reply, tx);
+                deliver(&nodes, p, Some(&history));
+                if let Ok(resp) = rx.await {
+                    let size = resp.as_ref().map_or(0, |r| r.len());
+                    tokio::time::sleep(links.delay(&to, &from, size)).await;
+                    let _ = reply.send(resp);
+                }
+            });
         }
     }
 }
+
+/// Queue a package at its node, logging it in `history` if queued.
+pub(crate) fn deliver(
+    nodes: &Mutex<HashMap<String, Endpoint>>,
+    p: NetworkPackage,
+    history: Option<&History>,
+) {
+    let node = nodes.lock().unwrap().get(&*p.to).cloned();
+    if let Some(x) = node {
+        // Never wait for a busy node, which would stall all others.
+        x.deliver_recorded(p, history);
+    } else {
+        warn!("node not found");
+    }
+}
@@ -1,12 +1,288 @@
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::error::{MessageTooLarge, Overloaded};
+use crate::history::History;
 use crate::network::NetworkPackage;
+use crate::{batch, compress};
 use anyhow::Result;
-use tokio::sync::mpsc::Sender;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+/// Lane a request is queued in.
+///
+/// A server handles all queued high-priority requests before normal ones, so
+/// that control traffic does not wait behind bulk requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
+}
+
+/// Limits of a server.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Number of requests queued in each lane before new ones are rejected
+    /// as overloaded.
+    pub queue_capacity: usize,
+    /// Maximum size in bytes of an encoded request.
+    pub max_message_size: usize,
+    /// Responses of at least this many bytes are [compressed](crate::compress)
+    /// for callers accepting it, never if `None`.
+    ///
+    /// Clients created by [`register_service_with_config`](crate::Network::register_service_with_config)
+    /// compress requests above the same threshold.
+    pub compress_threshold: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 100,
+            max_message_size: 4 << 20,
+            compress_threshold: None,
+        }
+    }
+}
+
+/// Snapshot of the request queue of a server.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueStats {
+    /// Requests waiting to be handled.
+    pub depth: usize,
+    /// Largest depth observed.
+    pub max_depth: usize,
+    /// Requests queued.
+    pub delivered: u64,
+    /// Requests queued in the high-priority lane.
+    pub high_priority: u64,
+    /// Requests rejected since the queue was full.
+    pub overloaded: u64,
+    /// Requests rejected since they exceed the maximum message size.
+    pub too_large: u64,
+    /// Size of compressed payloads, requests and responses, before compression.
+    pub uncompressed_bytes: u64,
+    /// Size of compressed payloads after compression.
+    pub compressed_bytes: u64,
+}
+
+impl QueueStats {
+    /// Uncompressed size divided by compressed size of compressed payloads.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            1.0
+        } else {
+            self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    delivered: AtomicU64,
+    high_priority: AtomicU64,
+    overloaded: AtomicU64,
+    too_large: AtomicU64,
+    uncompressed_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+}
+
+impl Metrics {
+    fn compressed(&self, uncompressed: usize, compressed: usize) {
+        self.uncompressed_bytes
+            .fetch_add(uncompressed as u64, Ordering::Relaxed);
+        self.compressed_bytes
+            .fetch_add(compressed as u64, Ordering::Relaxed);
+    }
+}
+
+/// Sending half of the request queue of a server.
+///
+/// Delivery never waits: requests that cannot be queued are answered with
+/// [`Overloaded`] or [`MessageTooLarge`] immediately.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    tx: Sender<NetworkPackage>,
+    high_tx: Sender<NetworkPackage>,
+    config: Config,
+    metrics: Arc<Metrics>,
+}
+
+/// Receiving half of the request queue of a server.
+#[derive(Debug)]
+pub struct Mailbox {
+    rx: Receiver<NetworkPackage>,
+    high_rx: Receiver<NetworkPackage>,
+    config: Config,
+    metrics: Arc<Metrics>,
+}
+
+/// Create a request queue limited by `config`.
+pub fn channel(config: Config) -> (Endpoint, Mailbox) {
+    let (tx, rx) = mpsc::channel(config.queue_capacity);
+    let (high_tx, high_rx) = mpsc::channel(config.queue_capacity);
+    let metrics = Arc::new(Metrics::default());
+    (
+        Endpoint {
+            tx,
+            high_tx,
+            config,
+            metrics: metrics.clone(),
+        },
+        Mailbox {
+            rx,
+            high_rx,
+            config,
+            metrics,
+        },
+    )
+}
+
+impl Endpoint {
+    /// Queue a request, or reply an error if it is rejected.
+    ///
+    /// Each call of a [batched](crate::batch) package is queued on its own.
+    pub fn deliver(&self, p: NetworkPackage) {
+        self.deliver_recorded(p, None);
+    }
+
+    /// Queue a request like [`deliver`](Self::deliver), and log it in
+    /// `history` once it is queued.
+    pub(crate) fn deliver_recorded(&self, p: NetworkPackage, history: Option<&History>) {
+        let p = match batch::unpack(p) {
+            Ok(calls) => {
+                for p in calls {
+                    self.deliver_recorded(p, history);
+                }
+                return;
+            }
+            Err(p) => p,
+        };
+        let m = &self.metrics;
+        if p.data.len() > self.config.max_message_size {
+            m.too_large.fetch_add(1, Ordering::Relaxed);
+            let e = MessageTooLarge {
+                size: p.data.len(),
+                limit: self.config.max_message_size,
+            };
+            let _ = p.reply.send(Err(e.into()));
+            return;
+        }
+
+        // Decode before sending, since the server takes the request.
+        let message = history.and_then(|h| h.message(&p));
+        // Count before sending, since the server may dequeue it at once.
+        let depth = m.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, high) = match p.priority {
+            Priority::Normal => (&self.tx, false),
+            Priority::High => (&self.high_tx, true),
+        };
+        match tx.try_send(p) {
+            Ok(()) => {
+                m.delivered.fetch_add(1, Ordering::Relaxed);
+                if high {
+                    m.high_priority.fetch_add(1, Ordering::Relaxed);
+                }
+                m.max_depth.fetch_max(depth, Ordering::Relaxed);
+                if let (Some(history), Some(message)) = (history, message) {
+                    history.push(message);
+                }
+            }
+            Err(TrySendError::Full(p)) => {
+                m.depth.fetch_sub(1, Ordering::Relaxed);
+                m.overloaded.fetch_add(1, Ordering::Relaxed);
+                let _ = p.reply.send(Err(Overloaded.into()));
+            }
+            Err(TrySendError::Closed(_)) => {
+                m.depth.fetch_sub(1, Ordering::Relaxed);
+                warn!("send to node failed, dropped");
+            }
+        }
+    }
+
+    /// Statistics of the queue.
+    pub fn stats(&self) -> QueueStats {
+        let m = &self.metrics;
+        QueueStats {
+            depth: m.depth.load(Ordering::Relaxed),
+            max_depth: m.max_depth.load(Ordering::Relaxed),
+            delivered: m.delivered.load(Ordering::Relaxed),
+            high_priority: m.high_priority.load(Ordering::Relaxed),
+            overloaded: m.overloaded.load(Ordering::Relaxed),
+            too_large: m.too_large.load(Ordering::Relaxed),
+            uncompressed_bytes: m.uncompressed_bytes.load(Ordering::Relaxed),
+            compressed_bytes: m.compressed_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Mailbox {
+    /// Receive the next request, high-priority ones first.
+    async fn next(&mut self) -> Option<NetworkPackage> {
+        if let Ok(p) = self.high_rx.try_recv() {
+            return Some(p);
+        }
+        tokio::select! {
+            Some(p) = self.high_rx.recv() => Some(p),
+            p = self.rx.recv() => p,
+        }
+    }
+
+    /// Receive the next request, decompressed.
+    ///
+    /// Requests that fail to decompress are answered with the error and skipped.
+    pub async fn recv(&mut self) -> Option<NetworkPackage> {
+        loop {
+            let mut p = self.next().await?;
+            self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+            if !compress::is_compressed(&p.data) {
+                return Some(p);
+            }
+            let size = p.data.len();
+            match compress::decode(p.data, self.config.max_message_size) {
+                Ok(data) => {
+                    self.metrics.compressed(data.len(), size);
+                    p.data = data;
+                    return Some(p);
+                }
+                Err(e) => {
+                    let _ = p.reply.send(Err(e));
+                }
+            }
+        }
+    }
+
+    /// Compress an encoded response if the caller accepts `compression`.
+    pub fn encode_response(&self, resp: String, compression: bool) -> String {
+        if !compression {
+            return resp;
+        }
+        let size = resp.len();
+        let resp = compress::encode(resp, self.config.compress_threshold);
+        if compress::is_compressed(&resp) {
+            self.metrics.compressed(size, resp.len());
+        }
+        resp
+    }
+}
 
 #[async_trait::async_trait]
 pub trait Server {
     type Service;
-    fn from_service(svc: Self::Service) -> Self;
-    fn client_chan(&self) -> Sender<NetworkPackage>;
+    fn from_service(svc: Self::Service) -> Self
+    where
+        Self: Sized,
+    {
+        Self::with_config(svc, Config::default())
+    }
+    fn with_config(svc: Self::Service, config: Config) -> Self;
+    fn endpoint(&self) -> Endpoint;
     async fn handle(&mut self) -> Result<()>;
     async fn run(&mut self) -> Result<()> {
         loop {
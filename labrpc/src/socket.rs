@@ -0,0 +1,305 @@
+//! Socket transport for nodes of a [`Network`](crate::Network).
+//!
+//! [`listen`] exposes all nodes of a network on a TCP listener, and [`connect`]
+//! returns a channel to create clients of remote nodes with
+//! [`Client::from_server`](crate::client::Client::from_server).
+//! [`listen_unix`] and [`connect_unix`] do the same over Unix domain sockets.
+//! Each frame on the wire is a big-endian `u32` length followed by a
+//! JSON-encoded [`Frame`].
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    sync::{
+        mpsc::{self, Sender},
+        oneshot,
+    },
+};
+
+use crate::{
+    error::{MessageTooLarge, Overloaded},
+    network::{deliver, NetworkPackage, Nodes},
+    server::Priority,
+    trace::TraceContext,
+};
+
+/// Largest frame accepted from a peer.
+pub const MAX_FRAME_SIZE: usize = 64 << 20;
+
+/// Unit of the wire protocol.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Frame {
+    /// Request to node `to`, answered by a response with the same id.
+    Request {
+        id: u64,
+        to: String,
+        data: String,
+        trace: TraceContext,
+        #[serde(default)]
+        compression: bool,
+        #[serde(default)]
+        priority: Priority,
+    },
+    /// Response to the request with the same id.
+    Response {
+        id: u64,
+        result: std::result::Result<String, WireError>,
+    },
+}
+
+/// Failed call carried over the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WireError {
+    /// See [`Overloaded`].
+    Overloaded,
+    /// See [`MessageTooLarge`].
+    TooLarge { size: usize, limit: usize },
+    /// Any other failure.
+    Other(String),
+}
+
+impl From<anyhow::Error> for WireError {
+    fn from(e: anyhow::Error) -> Self {
+        if e.is::<Overloaded>() {
+            WireError::Overloaded
+        } else if let Some(e) = e.downcast_ref::<MessageTooLarge>() {
+            WireError::TooLarge {
+                size: e.size,
+                limit: e.limit,
+            }
+        } else {
+            WireError::Other(e.to_string())
+        }
+    }
+}
+
+impl From<WireError> for anyhow::Error {
+    fn from(e: WireError) -> Self {
+        match e {
+            WireError::Overloaded => Overloaded.into(),
+            WireError::TooLarge { size, limit } => MessageTooLarge { size, limit }.into(),
+            WireError::Other(e) => anyhow!(e),
+        }
+    }
+}
+
+/// Read a frame, or `None` if the peer closed the connection.
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> Result<Option<Frame>> {
+    let len = match r.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(MessageTooLarge {
+            size: len,
+            limit: MAX_FRAME_SIZE,
+        }
+        .into());
+    }
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf).await?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+/// Write a frame.
+pub async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, frame: &Frame) -> Result<()> {
+    let buf = serde_json::to_vec(frame)?;
+    w.write_u32(buf.len() as u32).await?;
+    w.write_all(&buf).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+/// Serve requests of the peer `from` to `nodes` until the connection closes.
+///
+/// `from` must have been authenticated, or be empty if unknown.
+pub async fn serve<S>(stream: S, nodes: Nodes, from: String) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    serve_allowed(stream, nodes, from, |_| true).await
+}
+
+/// Serve requests of the peer `from` like [`serve`], refusing those to the
+/// nodes `allowed` returns false for.
+pub async fn serve_allowed<S, F>(stream: S, nodes: Nodes, from: String, allowed: F) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+    F: Fn(&str) -> bool,
+{
+    let from: Arc<str> = from.into();
+    let (mut r, mut w) = io::split(stream);
+    let (out_tx, mut out_rx) = mpsc::channel::<Frame>(100);
+    tokio::spawn(async move {
+        while let Some(frame) = out_rx.recv().await {
+            if let Err(e) = write_frame(&mut w, &frame).await {
+                warn!("write to socket failed: {}", e);
+                break;
+            }
+        }
+    });
+
+    while let Some(frame) = read_frame(&mut r).await? {
+        match frame {
+            Frame::Request {
+                id,
+                to,
+                data,
+                trace,
+                compression,
+                priority,
+            } => {
+                trace!("socket recv {} from {:?}", id, from);
+                let (reply, rx) = oneshot::channel();
+                if allowed(&to) {
+                    deliver(
+                        &nodes,
+                        NetworkPackage {
+                            from: from.clone(),
+                            to: to.into(),
+                            reply,
+                            data,
+                            trace,
+                            compression,
+                            priority,
+                        },
+                        None,
+                    );
+                } else {
+                    let _ = reply.send(Err(anyhow!("{:?} may not call {}", from, to)));
+                }
+                let out_tx = out_tx.clone();
+                tokio::spawn(async move {
+                    let result = match rx.await {
+                        Ok(resp) => resp.map_err(WireError::from),
+                        Err(_) => Err(WireError::Other("unable to receive from server".into())),
+                    };
+                    let _ = out_tx.send(Frame::Response { id, result }).await;
+                });
+            }
+            Frame::Response { id, .. } => warn!("unexpected response {} from client", id),
+        }
+    }
+    Ok(())
+}
+
+/// Accept connections and serve requests to `nodes`.
+///
+/// Peers are not authenticated, so anyone reaching the listener may call all
+/// nodes, see [`tls`](crate::tls) otherwise.
+pub async fn listen(listener: TcpListener, nodes: Nodes) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let nodes = nodes.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, nodes, String::new()).await {
+                warn!("connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Accept connections on a Unix domain socket and serve requests to `nodes`.
+pub async fn listen_unix(listener: UnixListener, nodes: Nodes) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let nodes = nodes.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, nodes, String::new()).await {
+                warn!("unix connection failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Send requests over a connected stream.
+///
+/// Returns the channel to create clients with. Pending calls fail when the
+/// connection closes.
+pub fn spawn_connection<S>(stream: S) -> Sender<NetworkPackage>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::channel::<NetworkPackage>(100);
+    let (mut r, mut w) = io::split(stream);
+    // Reply channels of pending calls, or `None` once the connection closed.
+    let pending = Arc::new(Mutex::new(Some(HashMap::new())));
+
+    let pending_w = pending.clone();
+    tokio::spawn(async move {
+        let mut next_id: u64 = 0;
+        while let Some(p) = rx.recv().await {
+            next_id += 1;
+            let id = next_id;
+            match pending_w.lock().unwrap().as_mut() {
+                Some(pending) => pending.insert(id, p.reply),
+                None => break,
+            };
+            let frame = Frame::Request {
+                id,
+                to: p.to.to_string(),
+                data: p.data,
+                trace: p.trace,
+                compression: p.compression,
+                priority: p.priority,
+            };
+            if let Err(e) = write_frame(&mut w, &frame).await {
+                warn!("write to socket failed: {}", e);
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        loop {
+            match read_frame(&mut r).await {
+                Ok(Some(Frame::Response { id, result })) => {
+                    let reply = pending
+                        .lock()
+                        .unwrap()
+                        .as_mut()
+                        .and_then(|p| p.remove(&id));
+                    if let Some(reply) = reply {
+                        let _ = reply.send(result.map_err(Into::into));
+                    }
+                }
+                Ok(Some(Frame::Request { id, .. })) => {
+                    warn!("unexpected request {} from server", id)
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("read from socket failed: {}", e);
+                    break;
+                }
+            }
+        }
+        // Fail all pending calls.
+        pending.lock().unwrap().take();
+    });
+
+    tx
+}
+
+/// Connect to nodes listening on `addr`.
+pub async fn connect(addr: SocketAddr) -> Result<Sender<NetworkPackage>> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    Ok(spawn_connection(stream))
+}
+
+/// Connect to nodes listening on the Unix domain socket at `path`.
+pub async fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Sender<NetworkPackage>> {
+    let stream = UnixStream::connect(path).await?;
+    Ok(spawn_connection(stream))
+}
@@ -0,0 +1,185 @@
+//! Mutual TLS for the [socket](crate::socket) transport.
+//!
+//! Nodes authenticate each other by pinned certificates, which may be
+//! self-signed. A listener only accepts peers it trusts, and requests of a
+//! peer are marked as sent by the node identity its certificate maps to.
+//!
+//! Any trusted peer may call any node behind a listener, unless the node has
+//! an allow-list set by [`TlsConfig::allow`]. Plain [`socket`](crate::socket)
+//! listeners authenticate nobody, and let anyone reaching them call all
+//! nodes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use futures_rustls::{
+    rustls::{
+        Certificate, ClientCertVerified, ClientCertVerifier, ClientConfig, DistinguishedNames,
+        PrivateKey, RootCertStore, ServerCertVerified, ServerCertVerifier, ServerConfig, Session,
+        TLSError,
+    },
+    webpki::{DNSName, DNSNameRef},
+    TlsAcceptor, TlsConnector,
+};
+use log::warn;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Sender,
+};
+use tokio_util::compat::{FuturesAsyncReadCompatExt, Tokio02AsyncReadCompatExt};
+
+use crate::{
+    network::{NetworkPackage, Nodes},
+    socket::{serve_allowed, spawn_connection},
+};
+
+/// Certificate of a node and certificates of the peers it trusts.
+#[derive(Clone)]
+pub struct TlsConfig {
+    cert: Certificate,
+    key: PrivateKey,
+    /// Node identity by DER-encoded certificate.
+    peers: HashMap<Vec<u8>, String>,
+    /// Identities allowed to call each node, all trusted ones if not listed.
+    allowed: HashMap<String, HashSet<String>>,
+}
+
+impl TlsConfig {
+    /// Create a config from a DER-encoded certificate and PKCS#8 private key.
+    pub fn new(cert: Vec<u8>, key: Vec<u8>) -> Self {
+        Self {
+            cert: Certificate(cert),
+            key: PrivateKey(key),
+            peers: HashMap::new(),
+            allowed: HashMap::new(),
+        }
+    }
+
+    /// Trust node `id` presenting the DER-encoded certificate `cert`.
+    pub fn trust(mut self, id: impl Into<String>, cert: Vec<u8>) -> Self {
+        self.peers.insert(cert, id.into());
+        self
+    }
+
+    /// Let peer `id` call `node` served by the listener, and only the peers
+    /// allowed this way once one is.
+    pub fn allow(mut self, node: impl Into<String>, id: impl Into<String>) -> Self {
+        self.allowed
+            .entry(node.into())
+            .or_default()
+            .insert(id.into());
+        self
+    }
+
+    fn may_call(&self, id: &str, node: &str) -> bool {
+        match self.allowed.get(node) {
+            Some(ids) => ids.contains(id),
+            None => true,
+        }
+    }
+
+    fn identity(&self, certs: &[Certificate]) -> Option<String> {
+        certs.first().and_then(|c| self.peers.get(&c.0)).cloned()
+    }
+}
+
+/// Accepts trusted client certificates only.
+struct TrustedClients(HashMap<Vec<u8>, String>);
+
+impl ClientCertVerifier for TrustedClients {
+    fn client_auth_root_subjects(&self, _sni: Option<&DNSName>) -> Option<DistinguishedNames> {
+        Some(DistinguishedNames::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[Certificate],
+        _sni: Option<&DNSName>,
+    ) -> Result<ClientCertVerified, TLSError> {
+        match presented_certs.first() {
+            Some(c) if self.0.contains_key(&c.0) => Ok(ClientCertVerified::assertion()),
+            _ => Err(TLSError::General("untrusted client certificate".into())),
+        }
+    }
+}
+
+/// Accepts the certificate of one server only.
+struct PinnedServer(Vec<u8>);
+
+impl ServerCertVerifier for PinnedServer {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        match presented_certs.first() {
+            Some(c) if c.0 == self.0 => Ok(ServerCertVerified::assertion()),
+            _ => Err(TLSError::General("unexpected server certificate".into())),
+        }
+    }
+}
+
+/// Accept TLS connections of trusted peers and serve their requests to the
+/// `nodes` they are allowed to call.
+pub async fn listen(listener: TcpListener, nodes: Nodes, config: TlsConfig) -> Result<()> {
+    let mut server = ServerConfig::new(Arc::new(TrustedClients(config.peers.clone())));
+    server.set_single_cert(vec![config.cert.clone()], config.key.clone())?;
+    let acceptor = TlsAcceptor::from(Arc::new(server));
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let nodes = nodes.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            let res: Result<()> = async {
+                let stream = acceptor.accept(stream.compat()).await?;
+                let id = stream
+                    .get_ref()
+                    .1
+                    .get_peer_certificates()
+                    .and_then(|certs| config.identity(&certs))
+                    .ok_or_else(|| anyhow!("unknown peer"))?;
+                let allowed = |node: &str| config.may_call(&id, node);
+                serve_allowed(stream.compat(), nodes, id.clone(), allowed).await
+            }
+            .await;
+            if let Err(e) = res {
+                warn!("tls connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Connect to the trusted node `server` listening on `addr`.
+pub async fn connect(
+    addr: std::net::SocketAddr,
+    config: TlsConfig,
+    server: &str,
+) -> Result<Sender<NetworkPackage>> {
+    let server_cert = config
+        .peers
+        .iter()
+        .find(|(_, id)| id.as_str() == server)
+        .map(|(cert, _)| cert.clone())
+        .ok_or_else(|| anyhow!("untrusted server {}", server))?;
+
+    let mut client = ClientConfig::new();
+    client.set_single_client_cert(vec![config.cert], config.key)?;
+    client
+        .dangerous()
+        .set_certificate_verifier(Arc::new(PinnedServer(server_cert)));
+    let connector = TlsConnector::from(Arc::new(client));
+
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    // The server is identified by its certificate rather than by name.
+    let name = DNSNameRef::try_from_ascii_str("labrpc").expect("valid dns name");
+    let stream = connector.connect(name, stream.compat()).await?;
+    Ok(spawn_connection(stream.compat()))
+}
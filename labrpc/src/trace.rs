@@ -0,0 +1,230 @@
+//! Tracing of calls across services.
+//!
+//! Every client call opens a `call` span and every handled request opens a
+//! `handle` span. Each call gets a random id, recorded as the `call_id` field
+//! of its span and carried in [`TraceContext`] to the `handle` span as its
+//! `caller` field. Ids of spans are local to a process and reused once
+//! closed, so the caller is never the parent of the `handle` span itself.
+//! Spans can be exported with [`ChromeLayer`] and viewed in `chrome://tracing`
+//! or [Perfetto](https://ui.perfetto.dev).
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Span, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    Layer,
+};
+
+/// Environment variable of the file to export traces to, see [`init_from_env`].
+pub const TRACE_ENV: &str = "LABRPC_TRACE";
+
+/// Trace context propagated with each request.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TraceContext {
+    call: Option<u64>,
+}
+
+impl TraceContext {
+    /// Context of a new call from the current span, whose id is recorded in
+    /// the `call_id` field of the span if it has one.
+    pub fn current() -> Self {
+        let span = Span::current();
+        if span.is_disabled() {
+            return Self::default();
+        }
+        let call = rand::random();
+        span.record("call_id", call);
+        Self { call: Some(call) }
+    }
+
+    /// Id of the call of the caller, if it was traced.
+    pub fn caller(&self) -> Option<u64> {
+        self.call
+    }
+}
+
+/// Layer writing spans as a Chrome trace.
+///
+/// Spans sharing the same root are written as nested async events with the
+/// same id, so that each request tree is shown on its own track. A `handle`
+/// span joins the tree of its caller if the `call` span is still open in this
+/// process, and starts a tree of its own otherwise.
+pub struct ChromeLayer {
+    start: Instant,
+    events: Arc<Mutex<Vec<Value>>>,
+    /// Roots of the open `call` spans by call id.
+    calls: Mutex<HashMap<u64, u64>>,
+}
+
+/// Writes the trace file when flushed or dropped.
+pub struct FlushGuard {
+    path: PathBuf,
+    events: Arc<Mutex<Vec<Value>>>,
+}
+
+/// Root of the span tree and name of the span, kept until it is closed.
+struct Event {
+    root: u64,
+    name: String,
+    call: Option<u64>,
+}
+
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+impl ChromeLayer {
+    /// Create a layer exporting to the file at `path`.
+    pub fn new<P: AsRef<Path>>(path: P) -> (Self, FlushGuard) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let guard = FlushGuard {
+            path: path.as_ref().to_path_buf(),
+            events: events.clone(),
+        };
+        (
+            Self {
+                start: Instant::now(),
+                events,
+                calls: Mutex::new(HashMap::new()),
+            },
+            guard,
+        )
+    }
+
+    fn push(&self, ph: &str, name: &str, root: u64, args: Value) {
+        let ts = self.start.elapsed().as_secs_f64() * 1e6;
+        self.events.lock().unwrap().push(json!({
+            "name": name,
+            "cat": "rpc",
+            "ph": ph,
+            "ts": ts,
+            "pid": 1,
+            "tid": 1,
+            "id": format!("{:#x}", root),
+            "args": args,
+        }));
+    }
+}
+
+impl<S> Layer<S> for ChromeLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist in registry");
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+
+        let caller = fields.0.get("caller").and_then(|c| c.as_u64());
+        let root = caller
+            .and_then(|c| self.calls.lock().unwrap().get(&c).copied())
+            .or_else(|| {
+                span.parent()
+                    .and_then(|p| p.extensions().get::<Event>().map(|e| e.root))
+            })
+            .unwrap_or_else(|| id.into_u64());
+        let name = match fields.0.get("method").and_then(|m| m.as_str()) {
+            Some(method) => format!("{} {}", span.name(), method),
+            None => span.name().to_string(),
+        };
+        self.push("b", &name, root, Value::Object(fields.0));
+        span.extensions_mut().insert(Event {
+            root,
+            name,
+            call: None,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        let call = match fields.0.get("call_id").and_then(|c| c.as_u64()) {
+            Some(call) => call,
+            None => return,
+        };
+        if let Some(span) = ctx.span(id) {
+            if let Some(e) = span.extensions_mut().get_mut::<Event>() {
+                e.call = Some(call);
+                self.calls.lock().unwrap().insert(call, e.root);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            if let Some(e) = span.extensions().get::<Event>() {
+                if let Some(call) = e.call {
+                    self.calls.lock().unwrap().remove(&call);
+                }
+                self.push("e", &e.name, e.root, json!({}));
+            }
+        }
+    }
+}
+
+impl FlushGuard {
+    /// Write all spans recorded so far.
+    pub fn flush(&self) -> Result<()> {
+        let events = self.events.lock().unwrap();
+        let file = BufWriter::new(File::create(&self.path)?);
+        serde_json::to_writer(file, &json!({ "traceEvents": *events }))?;
+        Ok(())
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::error!("failed to write trace to {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Export traces to the file named by [`TRACE_ENV`], if it is set.
+///
+/// Traces are written when the returned guard is dropped.
+pub fn init_from_env() -> Option<FlushGuard> {
+    let path = std::env::var_os(TRACE_ENV)?;
+    let (layer, guard) = ChromeLayer::new(path);
+    let subscriber = tracing_subscriber::registry().with(layer);
+    if tracing::subscriber::set_global_default(subscriber).is_err() {
+        log::warn!("global tracing subscriber already set");
+        return None;
+    }
+    Some(guard)
+}
@@ -0,0 +1,64 @@
+labrpc::service! {
+    service echo {
+        fn say(x: String) -> String;
+    }
+}
+
+use echo::{Client, Server, Service};
+use labrpc::{
+    anyhow::{anyhow, Result},
+    batch::{self, Batcher},
+    client::Client as _,
+    futures::future,
+    tokio, Network,
+};
+use std::time::Duration;
+
+struct Echo;
+
+#[labrpc::async_trait]
+impl Service for Echo {
+    async fn say(&mut self, x: String) -> Result<String> {
+        if x.is_empty() {
+            return Err(anyhow!("empty"));
+        }
+        Ok(x)
+    }
+}
+
+#[tokio::test]
+async fn test_batch() {
+    let mut net = Network::new();
+    let (_, server) = net.register_service::<Server<Echo>, Client, _, _>("echo".into(), || Echo);
+    tokio::spawn(server);
+    let batcher = Batcher::new(
+        net.tx.clone(),
+        batch::Config {
+            window: Duration::from_millis(10),
+            max_size: 8,
+        },
+    );
+    let nodes = net.nodes.clone();
+    tokio::spawn(async move { net.run().await });
+    while nodes.lock().unwrap().get("echo").is_none() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let c = Client::from_server("echo".into(), batcher.sender());
+    let calls = (0..20).map(|i| {
+        let c = c.clone();
+        async move { c.say(i.to_string()).await.unwrap() }
+    });
+    let resps = future::join_all(calls).await;
+    assert_eq!(resps, (0..20).map(|i| i.to_string()).collect::<Vec<_>>());
+
+    let stats = batcher.stats();
+    assert_eq!(stats.calls, 20);
+    assert_eq!(stats.max_size, 8);
+    assert!(stats.batches < 20);
+
+    // Each call of a batch fails on its own.
+    let (ok, err) = future::join(c.say("x".into()), c.say(String::new())).await;
+    assert_eq!(ok.unwrap(), "x");
+    assert!(err.is_err());
+}
@@ -0,0 +1,49 @@
+labrpc::service! {
+    service echo {
+        fn say(x: String) -> String;
+    }
+}
+
+use echo::{Client, Server, Service};
+use labrpc::{anyhow::Result, server::Config, tokio, Network};
+use std::time::Duration;
+
+struct Echo;
+
+#[labrpc::async_trait]
+impl Service for Echo {
+    async fn say(&mut self, x: String) -> Result<String> {
+        Ok(x)
+    }
+}
+
+#[tokio::test]
+async fn test_compress() {
+    let mut net = Network::new();
+    let config = Config {
+        compress_threshold: Some(1024),
+        ..Config::default()
+    };
+    let (c, server) = net.register_service_with_config::<Server<Echo>, Client, _, _>(
+        "echo".into(),
+        config,
+        || Echo,
+    );
+    tokio::spawn(server);
+    let nodes = net.nodes.clone();
+    tokio::spawn(async move { net.run().await });
+    while nodes.lock().unwrap().get("echo").is_none() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let stats = || nodes.lock().unwrap().get("echo").unwrap().stats();
+
+    // Small payloads are sent as they are.
+    assert_eq!(c.say("hello".into()).await.unwrap(), "hello");
+    assert_eq!(stats().compressed_bytes, 0);
+
+    let x = "hello".repeat(10000);
+    assert_eq!(c.say(x.clone()).await.unwrap(), x);
+    let stats = stats();
+    assert!(stats.uncompressed_bytes > 2 * x.len() as u64);
+    assert!(stats.compression_ratio() > 10.0);
+}
@@ -0,0 +1,38 @@
+use labrpc::{
+    failover::{Failover, Policy},
+    futures::future::ready,
+    tokio,
+};
+
+/// Replicas `0..3`, of which only those in `up` reply.
+async fn call(f: &Failover<usize>, up: &[usize]) -> Result<usize, String> {
+    f.call(|i| {
+        ready(if up.contains(&i) {
+            Ok(i)
+        } else {
+            Err(format!("{} down", i))
+        })
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_failover() {
+    let f = Failover::new(vec![0, 1, 2]);
+    assert_eq!(call(&f, &[0, 1, 2]).await, Ok(0));
+    assert_eq!(call(&f, &[0, 1, 2]).await, Ok(1));
+    assert_eq!(call(&f, &[0]).await, Ok(0));
+
+    let f = Failover::new(vec![0, 1, 2]).policy(Policy::Sticky);
+    assert_eq!(call(&f, &[2]).await, Ok(2));
+    assert_eq!(call(&f, &[0, 1, 2]).await, Ok(2));
+
+    let f = Failover::new(vec![0, 1, 2]).policy(Policy::Leader);
+    f.set_leader(1);
+    assert_eq!(call(&f, &[0, 1, 2]).await, Ok(1));
+    assert_eq!(call(&f, &[0, 2]).await, Ok(2));
+    assert_eq!(call(&f, &[0, 1, 2]).await, Ok(1));
+
+    let f = Failover::new(vec![0, 1, 2]).max_attempts(4);
+    assert_eq!(call(&f, &[]).await, Err("0 down".to_string()));
+}
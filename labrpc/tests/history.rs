@@ -0,0 +1,55 @@
+use labrpc::{
+    anyhow::Result,
+    error::Overloaded,
+    network::NetworkPackage,
+    server::{self, Config, Priority},
+    tokio::{self, sync::oneshot},
+    trace::TraceContext,
+    Network,
+};
+
+/// A ping to node `to`, and the receiver of its reply.
+fn ping(to: &str) -> (NetworkPackage, oneshot::Receiver<Result<String>>) {
+    let (reply, rx) = oneshot::channel();
+    let p = NetworkPackage {
+        from: "client".into(),
+        to: to.into(),
+        reply,
+        data: r#"{"ping":{}}"#.into(),
+        trace: TraceContext::default(),
+        compression: false,
+        priority: Priority::Normal,
+    };
+    (p, rx)
+}
+
+#[tokio::test]
+async fn test_record_queued_only() {
+    let mut net = Network::new();
+    let history = net.record();
+    // A server that never takes requests, with room for one.
+    let (endpoint, _mailbox) = server::channel(Config {
+        queue_capacity: 1,
+        ..Config::default()
+    });
+    net.nodes.lock().unwrap().insert("server".into(), endpoint);
+    let tx = net.tx.clone();
+    tokio::spawn(async move { net.run().await });
+
+    let (p, _queued) = ping("server");
+    tx.send(p).await.unwrap();
+    let (p, overloaded) = ping("server");
+    tx.send(p).await.unwrap();
+    let e = overloaded.await.unwrap().unwrap_err();
+    assert!(e.is::<Overloaded>(), "{}", e);
+    // The package to a missing node is dropped with its reply channel.
+    let (p, missing) = ping("missing");
+    tx.send(p).await.unwrap();
+    assert!(missing.await.is_err());
+
+    let messages = history.messages();
+    assert_eq!(messages.len(), 1, "{:?}", messages);
+    assert_eq!(messages[0].seq, 0);
+    assert_eq!((&*messages[0].from, &*messages[0].to), ("client", "server"));
+    assert_eq!(messages[0].method(), Some("ping"));
+}
@@ -0,0 +1,118 @@
+labrpc::service! {
+    service hello {
+        fn say(a: i32, x: String) -> String;
+    }
+}
+
+use hello::{Client, Server, Service};
+use labrpc::{anyhow::Result, http::Gateway, tokio, Network};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+struct Hello;
+
+#[labrpc::async_trait]
+impl Service for Hello {
+    async fn say(&mut self, a: i32, x: String) -> Result<String> {
+        Ok(x.repeat(a as usize))
+    }
+}
+
+/// Serve the hello service on a gateway accepting bodies of at most 64 bytes.
+async fn gateway() -> SocketAddr {
+    let mut net = Network::new();
+    let (client, server) =
+        net.register_service::<Server<Hello>, Client, _, _>("hello".into(), || Hello);
+    tokio::spawn(server);
+    tokio::spawn(async move { net.run().await });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let gateway = Gateway::new().route(client).max_body_size(64);
+    tokio::spawn(async move { gateway.listen(listener).await });
+    addr
+}
+
+/// Send raw `request` and read the response until the gateway closes the
+/// connection, returning its status and body.
+async fn send(addr: SocketAddr, request: &[u8]) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+
+    let status = resp.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let body = resp.splitn(2, "\r\n\r\n").nth(1).unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+fn post(path: &str, body: &str) -> Vec<u8> {
+    format!(
+        "POST {} HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        path,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
+#[tokio::test]
+async fn test_post() {
+    let addr = gateway().await;
+    let (status, body) = send(addr, &post("/hello/say", r#"{"a": 3, "x": "hi"}"#)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!("hihihi"));
+
+    let (status, _) = send(addr, &post("/hello/say", r#"{"a": "x"}"#)).await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn test_unknown_service() {
+    let addr = gateway().await;
+    let (status, _) = send(addr, &post("/bye/say", "{}")).await;
+    assert_eq!(status, 404);
+    let (status, _) = send(addr, &post("/hello/shout", "{}")).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn test_oversized_body() {
+    let addr = gateway().await;
+    // Refused before the body is read, so it need not be sent.
+    let req = b"POST /hello/say HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\n";
+    let (status, _) = send(addr, req).await;
+    assert_eq!(status, 413);
+}
+
+#[tokio::test]
+async fn test_malformed_request() {
+    let addr = gateway().await;
+    let (status, _) = send(addr, b"GARBAGE\r\n\r\n").await;
+    assert_eq!(status, 400);
+
+    let req = b"POST /hello/say HTTP/1.1\r\nContent-Length: many\r\n\r\n";
+    let (status, _) = send(addr, req).await;
+    assert_eq!(status, 400);
+
+    let req = b"POST /hello/say HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+    let (status, _) = send(addr, req).await;
+    assert_eq!(status, 501);
+
+    let mut req = b"POST /hello/say HTTP/1.1\r\nX-Long: ".to_vec();
+    req.extend(vec![b'a'; 10 << 10]);
+    let (status, _) = send(addr, &req).await;
+    assert_eq!(status, 431);
+
+    let mut req = b"POST /hello/say HTTP/1.1\r\n".to_vec();
+    for i in 0..100 {
+        req.extend(format!("X-Header-{}: a\r\n", i).into_bytes());
+    }
+    req.extend(b"\r\n");
+    let (status, _) = send(addr, &req).await;
+    assert_eq!(status, 431);
+}
@@ -0,0 +1,60 @@
+labrpc::service! {
+    service echo {
+        fn say(x: String) -> String;
+    }
+}
+
+use echo::{Client, Server, Service};
+use labrpc::{anyhow::Result, link::Link, tokio, Network};
+use std::time::Duration;
+
+struct Echo;
+
+#[labrpc::async_trait]
+impl Service for Echo {
+    async fn say(&mut self, x: String) -> Result<String> {
+        Ok(x)
+    }
+}
+
+/// Time taken to echo `size` bytes over links like `link`.
+async fn round_trip(link: Link, size: usize) -> Duration {
+    let mut net = Network::new();
+    net.links().set_default(link);
+    let (c, server) = net.register_service::<Server<Echo>, Client, _, _>("echo".into(), || Echo);
+    tokio::spawn(server);
+    tokio::spawn(async move { net.run().await });
+
+    let start = tokio::time::Instant::now();
+    assert_eq!(c.say("a".repeat(size)).await.unwrap().len(), size);
+    start.elapsed()
+}
+
+/// Requests and responses are each delayed by the latency of the link plus
+/// the time to transmit them at its bandwidth.
+#[tokio::test]
+async fn test_delay() {
+    const LATENCY: Duration = Duration::from_millis(50);
+    const SIZE: usize = 10_000;
+
+    tokio::time::pause();
+
+    let elapsed = round_trip(Link::new(LATENCY), SIZE).await;
+    assert!(elapsed >= 2 * LATENCY, "{:?}", elapsed);
+    assert!(
+        elapsed < 2 * LATENCY + Duration::from_millis(5),
+        "{:?}",
+        elapsed
+    );
+
+    // Each way transmits the payload for a second, plus a few bytes of
+    // encoding.
+    let elapsed = round_trip(Link::new(LATENCY).bandwidth(SIZE as u64), SIZE).await;
+    let expected = 2 * (LATENCY + Duration::from_secs(1));
+    assert!(elapsed >= expected, "{:?}", elapsed);
+    assert!(
+        elapsed < expected + Duration::from_millis(20),
+        "{:?}",
+        elapsed
+    );
+}
@@ -0,0 +1,36 @@
+use labrpc::{
+    nemesis::{Action, Config, Nemesis},
+    Network,
+};
+
+/// Take `n` steps of a nemesis seeded by `seed` on two fresh networks.
+fn schedule(seed: u64, n: usize) -> Vec<Action> {
+    let config = Config {
+        max_crashed: 2,
+        ..Config::default()
+    };
+    let mut nemesis = Nemesis::new(seed, config)
+        .target(Network::new().faults(), vec!["acc-0", "acc-1", "acc-2"])
+        .target(Network::new().faults(), vec!["kv-0", "kv-1"]);
+    for _ in 0..n {
+        nemesis.step();
+    }
+    nemesis.history().to_vec()
+}
+
+#[test]
+fn test_same_seed() {
+    let actions = schedule(42, 200);
+    assert_eq!(actions, schedule(42, 200));
+    assert_ne!(actions, schedule(43, 200));
+
+    // All kinds of faults are exercised.
+    let crashed = |a: &Action| matches!(a, Action::Crash(_));
+    let restarted = |a: &Action| matches!(a, Action::Restart(_));
+    let partitioned = |a: &Action| matches!(a, Action::Partition(_));
+    let lossy = |a: &Action| matches!(a, Action::Loss(_));
+    for kind in &[crashed, restarted, partitioned, lossy] {
+        assert!(actions.iter().any(kind));
+    }
+    assert!(actions.contains(&Action::Heal));
+}
@@ -0,0 +1,67 @@
+labrpc::service! {
+    service worker {
+        fn work(i: u32) -> u32;
+        priority(high)
+        fn ping() -> ();
+    }
+}
+
+use labrpc::{anyhow::Result, futures::future, tokio, Network};
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use worker::{Client, Request, Server, Service};
+
+/// Counts finished work.
+struct Worker(Arc<AtomicU32>);
+
+#[labrpc::async_trait]
+impl Service for Worker {
+    async fn work(&mut self, i: u32) -> Result<u32> {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Ok(i)
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_priority() {
+    assert_eq!(Request::ping {}.priority(), labrpc::server::Priority::High);
+
+    let mut net = Network::new();
+    let done = Arc::new(AtomicU32::new(0));
+    let d = done.clone();
+    let (c, server) = net
+        .register_service::<Server<Worker>, Client, _, _>("worker".into(), move || {
+            Worker(d.clone())
+        });
+    tokio::spawn(server);
+    let nodes = net.nodes.clone();
+    tokio::spawn(async move { net.run().await });
+    while nodes.lock().unwrap().get("worker").is_none() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    // Queue bulk work, then ping while it is handled.
+    let works: Vec<_> = (0..50)
+        .map(|i| {
+            let c = c.clone();
+            tokio::spawn(async move { c.work(i).await.unwrap() })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(25)).await;
+    c.ping().await.unwrap();
+    assert!(done.load(Ordering::SeqCst) < 10);
+
+    future::join_all(works).await;
+    let stats = nodes.lock().unwrap().get("worker").unwrap().stats();
+    assert_eq!(stats.high_priority, 1);
+}
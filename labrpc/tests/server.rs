@@ -0,0 +1,86 @@
+use labrpc::{
+    anyhow::Result,
+    error::{MessageTooLarge, Overloaded},
+    network::NetworkPackage,
+    server::{self, Config, Priority},
+    tokio::{self, sync::oneshot},
+    trace::TraceContext,
+};
+
+/// A request carrying `data`, and the receiver of its reply.
+fn request(data: &str) -> (NetworkPackage, oneshot::Receiver<Result<String>>) {
+    let (reply, rx) = oneshot::channel();
+    let p = NetworkPackage {
+        from: "".into(),
+        to: "server".into(),
+        reply,
+        data: data.into(),
+        trace: TraceContext::default(),
+        compression: false,
+        priority: Priority::Normal,
+    };
+    (p, rx)
+}
+
+fn config() -> Config {
+    Config {
+        queue_capacity: 2,
+        max_message_size: 8,
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn test_overloaded() {
+    let (endpoint, mut mailbox) = server::channel(config());
+
+    let mut replies = Vec::new();
+    for _ in 0..3 {
+        let (p, rx) = request("ping");
+        endpoint.deliver(p);
+        replies.push(rx);
+    }
+    // The request past capacity is answered at once.
+    let e = replies.pop().unwrap().await.unwrap().unwrap_err();
+    assert!(e.is::<Overloaded>(), "{}", e);
+
+    let stats = endpoint.stats();
+    assert_eq!(stats.depth, 2);
+    assert_eq!(stats.max_depth, 2);
+    assert_eq!(stats.delivered, 2);
+    assert_eq!(stats.overloaded, 1);
+    assert_eq!(stats.too_large, 0);
+
+    // Queued requests are handled, then there is room again.
+    for _ in 0..2 {
+        assert_eq!(mailbox.recv().await.unwrap().data, "ping");
+    }
+    let (p, _rx) = request("ping");
+    endpoint.deliver(p);
+    let stats = endpoint.stats();
+    assert_eq!(stats.depth, 1);
+    assert_eq!(stats.delivered, 3);
+    assert_eq!(stats.overloaded, 1);
+}
+
+#[tokio::test]
+async fn test_message_too_large() {
+    let (endpoint, mut mailbox) = server::channel(config());
+
+    let (p, rx) = request("123456789");
+    endpoint.deliver(p);
+    let e = rx.await.unwrap().unwrap_err();
+    let e = e.downcast_ref::<MessageTooLarge>().unwrap();
+    assert_eq!((e.size, e.limit), (9, 8));
+
+    // A request of exactly the limit is queued.
+    let (p, _rx) = request("12345678");
+    endpoint.deliver(p);
+    assert_eq!(mailbox.recv().await.unwrap().data, "12345678");
+
+    let stats = endpoint.stats();
+    assert_eq!(stats.too_large, 1);
+    assert_eq!(stats.delivered, 1);
+    assert_eq!(stats.overloaded, 0);
+    assert_eq!(stats.depth, 0);
+}
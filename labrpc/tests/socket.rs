@@ -0,0 +1,101 @@
+labrpc::service! {
+    service echo {
+        fn say(x: String) -> String;
+    }
+}
+
+use echo::{Client, Server, Service};
+use labrpc::{anyhow::Result, client::Client as _, socket, tokio, Network};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+struct Echo;
+
+#[labrpc::async_trait]
+impl Service for Echo {
+    async fn say(&mut self, x: String) -> Result<String> {
+        Ok(x)
+    }
+}
+
+/// Spawn an echo node on a new network and return the network.
+async fn echo_network() -> Network {
+    let net = Network::new();
+    let (_, server) = net.register_service::<Server<Echo>, Client, _, _>("echo".into(), || Echo);
+    tokio::spawn(server);
+    while net.nodes.lock().unwrap().get("echo").is_none() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    net
+}
+
+#[tokio::test]
+async fn test_tcp() {
+    let net = echo_network().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(socket::listen(listener, net.nodes.clone()));
+
+    let c = Client::from_server("echo".into(), socket::connect(addr).await.unwrap());
+    assert_eq!(c.say("hello".into()).await.unwrap(), "hello");
+
+    let c = Client::from_server("none".into(), socket::connect(addr).await.unwrap());
+    assert!(c.say("hello".into()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_unix() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("echo.sock");
+
+    let net = echo_network().await;
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    tokio::spawn(socket::listen_unix(listener, net.nodes.clone()));
+
+    let c = Client::from_server("echo".into(), socket::connect_unix(&path).await.unwrap());
+    assert_eq!(c.say("hello".into()).await.unwrap(), "hello");
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn test_tls() {
+    use labrpc::tls::{self, TlsConfig};
+
+    let gen = || {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        (
+            cert.serialize_der().unwrap(),
+            cert.serialize_private_key_der(),
+        )
+    };
+    let (server_cert, server_key) = gen();
+    let (kv_cert, kv_key) = gen();
+    let (client_cert, client_key) = gen();
+    let (other_cert, other_key) = gen();
+
+    let net = echo_network().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = TlsConfig::new(server_cert.clone(), server_key)
+        .trust("kv-0", kv_cert.clone())
+        .trust("client", client_cert.clone())
+        .allow("echo", "kv-0");
+    tokio::spawn(tls::listen(listener, net.nodes.clone(), config));
+
+    let say = |cert, key| {
+        let config = TlsConfig::new(cert, key).trust("acc-0", server_cert.clone());
+        async move {
+            let tx = tls::connect(addr, config, "acc-0").await?;
+            Client::from_server("echo".into(), tx)
+                .say("hello".into())
+                .await
+        }
+    };
+    // Trusted and allowed peer.
+    assert_eq!(say(kv_cert, kv_key).await.unwrap(), "hello");
+    // Trusted peer not allowed to call the node.
+    assert!(say(client_cert, client_key).await.is_err());
+    // Peer unknown to the server, which may only fail once the handshake
+    // completes on the side of the server.
+    assert!(say(other_cert, other_key).await.is_err());
+}
@@ -0,0 +1,79 @@
+labrpc::service! {
+    service echo {
+        fn hold(ms: u64) -> ();
+        fn echo(x: u32) -> u32;
+    }
+}
+
+use echo::{Client, Server, Service};
+use labrpc::{anyhow::Result, tokio, trace::ChromeLayer, Network};
+use serde_json::Value;
+use std::time::Duration;
+use tracing_subscriber::layer::SubscriberExt;
+
+struct Echo;
+
+#[labrpc::async_trait]
+impl Service for Echo {
+    async fn hold(&mut self, ms: u64) -> Result<()> {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+        Ok(())
+    }
+
+    async fn echo(&mut self, x: u32) -> Result<u32> {
+        Ok(x)
+    }
+}
+
+/// Begin events of spans named `name` in a Chrome trace.
+fn begins<'a>(trace: &'a Value, name: &'a str) -> impl Iterator<Item = &'a Value> + 'a {
+    trace["traceEvents"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(move |e| e["ph"] == "b" && e["name"] == name)
+}
+
+/// A request handled after its caller gave up, and thus closed its span,
+/// neither links to the closed span nor to another one reusing its id.
+#[tokio::test]
+async fn test_caller_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace.json");
+    let (layer, guard) = ChromeLayer::new(&path);
+    let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+
+    let mut net = Network::new();
+    let (c, server) =
+        net.register_service::<Server<Echo>, Client, _, _>("echo".into(), move || Echo);
+    tokio::spawn(server);
+    tokio::spawn(async move { net.run().await });
+
+    // Queue a call behind a held server, then give it up.
+    let hold = {
+        let c = c.clone();
+        tokio::spawn(async move { c.hold(50).await.unwrap() })
+    };
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let give_up = tokio::time::timeout(Duration::from_millis(5), c.echo(1)).await;
+    assert!(give_up.is_err());
+    // Let new spans take the ids of the closed ones.
+    for _ in 0..10 {
+        tracing::info_span!("other").in_scope(|| ());
+    }
+    hold.await.unwrap();
+
+    assert_eq!(c.echo(2).await.unwrap(), 2);
+    guard.flush().unwrap();
+
+    let trace: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    let calls: Vec<_> = begins(&trace, "call echo").collect();
+    let handles: Vec<_> = begins(&trace, "handle echo").collect();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(handles.len(), 2);
+    // The request given up starts a tree of its own, the last one joins the
+    // tree of its caller.
+    assert_ne!(handles[0]["id"], calls[0]["id"]);
+    assert_eq!(handles[1]["id"], calls[1]["id"]);
+    assert!(handles[1]["args"]["caller"].is_u64());
+}
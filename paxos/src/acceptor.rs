@@ -1,9 +1,27 @@
 use std::path::Path;
 
 use super::AcceptorService;
-use crate::Persistor;
-use crate::Proposal;
-use labrpc::{anyhow::Result, random_error};
+use crate::{AcceptorError, Proposal};
+use crate::{Batch, Durability, Persistor, Storage};
+use labrpc::{anyhow::Result, futures::future::BoxFuture, random_error, Error};
+
+/// Key of the range promise, `(from, pid)` for all keys from `from` onward.
+const KEY_RANGE: &str = "range";
+/// Key of the largest key with an accepted proposal.
+const KEY_MAX_ACCEPTED: &str = "max_accepted";
+/// Key of the watermark below which instances are discarded.
+const KEY_TRUNCATED: &str = "truncated";
+
+/// Prefix of the state of instance `key`, which sorts like the key and
+/// before the other keys of the acceptor.
+fn instance(key: u64) -> String {
+    format!("{:020}", key)
+}
+
+/// Key of field `field` of the state of instance `key`.
+fn instance_key(key: u64, field: &str) -> String {
+    format!("{}:{}", instance(key), field)
+}
 
 /// A stateless acceptor
 pub struct Acceptor {
@@ -19,48 +37,159 @@ impl Acceptor {
             persistor: Persistor::new(path),
         }
     }
+
+    /// Create an acceptor keeping its states in `storage`.
+    pub fn with_storage<S: Storage + 'static>(storage: S) -> Self {
+        Self {
+            persistor: Persistor::with_storage(storage),
+        }
+    }
+
+    /// Make promises and accepted proposals as durable as `durability`
+    /// before replying, see [`Durability`].
+    pub fn with_durability(self, durability: Durability) -> Self {
+        Self {
+            persistor: self.persistor.with_durability(durability),
+        }
+    }
+
+    /// The pid promised for `key`, by a prepare of the key or of a range
+    /// containing it.
+    fn promised(&self, key: u64) -> Result<Option<u64>> {
+        let pid = self.persistor.get(instance_key(key, "pid"))?;
+        let range = self
+            .persistor
+            .get::<_, (u64, u64)>(KEY_RANGE)?
+            .filter(|&(from, _)| key >= from)
+            .map(|(_, pid)| pid);
+        Ok(pid.max(range))
+    }
+
+    /// Watermark below which instances are discarded.
+    fn truncated(&self) -> Result<u64> {
+        Ok(self.persistor.get(KEY_TRUNCATED)?.unwrap_or(0))
+    }
+
+    /// Refuse requests for `key` once it is discarded, or with its value
+    /// once it is decided.
+    fn check_open(&self, key: u64) -> Result<(), Error<AcceptorError>> {
+        let below = self.truncated()?;
+        if key < below {
+            return Err(Error::Service(AcceptorError::Truncated { below }));
+        }
+        match self.persistor.get(instance_key(key, "decided"))? {
+            Some(value) => Err(Error::Service(AcceptorError::Decided { value })),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reject `pid` if lower than `promised`.
+fn check(pid: u64, promised: Option<u64>) -> Result<(), Error<AcceptorError>> {
+    match promised {
+        Some(promised) if pid < promised => {
+            Err(Error::Service(AcceptorError::Rejected { promised }))
+        }
+        _ => Ok(()),
+    }
 }
 
 /// It is stateless (everything goes to persistor).
 /// Thus, we can simply test it by returning Error.
+///
+/// Each request updates the persistor by a single atomic write, so that
+/// failing at any point never leaves a promise and an accepted proposal
+/// inconsistent.
+///
+/// With [`Durability::GroupCommit`], replies wait for the sync of their write
+/// while the next requests are handled, so that writes of concurrent requests
+/// share a sync.
 #[labrpc::async_trait]
 impl AcceptorService for Acceptor {
-    async fn prepare(&mut self, key: u64, pid: u64) -> Result<Option<Proposal>> {
-        let key_pid = format!("{}:pid", key);
-        let key_accepted = format!("{}:accepted", key);
-        let newer = self.persistor.get(&key_pid)?.map_or_else(
-            || Some(pid),
-            |prev| {
-                if pid < prev {
-                    None
-                } else {
-                    Some(pid)
-                }
-            },
-        );
-        if let Some(pid) = newer {
-            random_error!(0.05);
-            self.persistor.set(&key_pid, &pid)?;
-        };
+    async fn prepare(
+        &mut self,
+        key: u64,
+        pid: u64,
+    ) -> Result<Option<Proposal>, Error<AcceptorError>> {
+        random_error!(0.05);
+        self.check_open(key)?;
+        check(pid, self.promised(key)?)?;
+        let mut batch = Batch::default();
+        batch.set(instance_key(key, "pid"), &pid)?;
+        self.persistor.write(batch)?;
 
+        let accepted = self.persistor.get(instance_key(key, "accepted"))?;
         random_error!(0.05);
-        Ok(self.persistor.get(&key_accepted)?)
+        Ok(accepted)
     }
-    async fn accept(&mut self, key: u64, pid: u64, value: String) -> Result<u64> {
-        let key_pid = format!("{}:pid", key);
-        let prev_pid = self.persistor.get(&key_pid)?.expect("unprepared");
-        if pid == prev_pid {
-            let key_accepted = format!("{}:accepted", key);
-
-            random_error!(0.05);
-
-            self.persistor
-                .set(&key_accepted, &Proposal { id: pid, value })?;
-        } else if pid > prev_pid {
-            panic!("Unexpected request without prepraration.");
+    async fn accept(
+        &mut self,
+        key: u64,
+        pid: u64,
+        value: String,
+    ) -> Result<(), Error<AcceptorError>> {
+        random_error!(0.05);
+        self.check_open(key)?;
+        // A proposal at least as new as the promise is accepted even without
+        // its prepare, which may have been lost, reordered or forgotten.
+        check(pid, self.promised(key)?)?;
+        let mut batch = Batch::default();
+        batch.set(instance_key(key, "pid"), &pid)?;
+        batch.set(instance_key(key, "accepted"), &Proposal { id: pid, value })?;
+        if self.persistor.get::<_, u64>(KEY_MAX_ACCEPTED)? < Some(key) {
+            batch.set(KEY_MAX_ACCEPTED, &key)?;
         }
+        self.persistor.write(batch)?;
+
         random_error!(0.05);
+        Ok(())
+    }
+    async fn prepare_range(
+        &mut self,
+        from: u64,
+        pid: u64,
+    ) -> Result<Vec<(u64, Proposal)>, Error<AcceptorError>> {
+        random_error!(0.05);
+        let range = self.persistor.get::<_, (u64, u64)>(KEY_RANGE)?;
+        check(pid, range.map(|(_, prev)| prev))?;
+        // A single range can only grow downward, promising the keys between
+        // the two starts to `pid` as well is still safe.
+        let range_from = range.map_or(from, |(prev_from, _)| from.min(prev_from));
+        let mut batch = Batch::default();
+        batch.set(KEY_RANGE, &(range_from, pid))?;
+        self.persistor.write(batch)?;
 
-        Ok(prev_pid)
+        let mut accepted = Vec::new();
+        if let Some(max) = self.persistor.get(KEY_MAX_ACCEPTED)? {
+            for key in from.max(self.truncated()?)..=max {
+                if let Some(p) = self.persistor.get(instance_key(key, "accepted"))? {
+                    accepted.push((key, p));
+                }
+            }
+        }
+        random_error!(0.05);
+        Ok(accepted)
+    }
+    async fn decide(&mut self, key: u64, value: String) -> Result<(), Error<AcceptorError>> {
+        if key < self.truncated()? {
+            return Ok(());
+        }
+        let mut batch = Batch::default();
+        batch.set(instance_key(key, "decided"), &value)?;
+        self.persistor.write(batch)?;
+        Ok(())
+    }
+    async fn truncate(&mut self, below: u64) -> Result<(), Error<AcceptorError>> {
+        if below <= self.truncated()? {
+            return Ok(());
+        }
+        let mut batch = Batch::default();
+        batch.delete_range(instance(0), instance(below));
+        batch.set(KEY_TRUNCATED, &below)?;
+        self.persistor.write(batch)?;
+        Ok(())
+    }
+    fn reply_after(&mut self) -> Option<BoxFuture<'static, Result<()>>> {
+        self.persistor.sync()
     }
 }
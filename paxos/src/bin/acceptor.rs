@@ -0,0 +1,47 @@
+//! Acceptor serving on a Unix domain socket, for running a cluster of
+//! acceptor processes on one machine.
+//!
+//! Built with the `bin` feature, e.g. `cargo run -p paxos --features bin --bin
+//! acceptor -- --socket acc.sock --db acc.db`.
+
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use labrpc::{anyhow::Result, socket, tokio, Network};
+use paxos::{Acceptor, AcceptorClient, AcceptorServer};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "acceptor", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"))]
+struct Opt {
+    /// Unix domain socket to listen on.
+    #[structopt(short, long)]
+    socket: PathBuf,
+
+    /// Directory of the acceptor database.
+    #[structopt(short, long)]
+    db: PathBuf,
+
+    /// Node id that clients send requests to.
+    #[structopt(short, long, default_value = "acc")]
+    id: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let opt = Opt::from_args();
+    let net = Network::new();
+    let db = opt.db;
+    let (_, server) = net
+        .register_service::<AcceptorServer<Acceptor>, AcceptorClient, _, _>(opt.id, move || {
+            Acceptor::new(db.clone())
+        });
+    tokio::spawn(server);
+
+    // A crashed process leaves its socket file behind.
+    let _ = std::fs::remove_file(&opt.socket);
+    let listener = tokio::net::UnixListener::bind(&opt.socket)?;
+    socket::listen_unix(listener, net.nodes.clone()).await
+}
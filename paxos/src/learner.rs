@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use super::LearnerService;
+use crate::{Batch, LearnerError, Persistor};
+use labrpc::{log::error, Error};
+
+/// A learner of chosen values.
+///
+/// Values are announced by proposers as they are chosen and kept durably.
+/// Announcements may be lost, so a learner not knowing a value does not mean
+/// that none was chosen.
+pub struct Learner {
+    persistor: Persistor,
+}
+
+impl Learner {
+    /// Create a learner from a given path.
+    ///
+    /// The path is used to store and retrieve learned values.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            persistor: Persistor::new(path),
+        }
+    }
+}
+
+#[labrpc::async_trait]
+impl LearnerService for Learner {
+    async fn chosen(&mut self, key: u64, value: String) -> Result<(), Error<LearnerError>> {
+        let key_chosen = format!("{}:chosen", key);
+        if let Some(prev) = self.persistor.get::<_, String>(&key_chosen)? {
+            if prev != value {
+                // Safety is broken, keep what was learned first and tell.
+                error!(
+                    "different values chosen for key {}: {:?} and {:?}",
+                    key, prev, value
+                );
+                return Err(Error::Service(LearnerError::Conflict { learned: prev }));
+            }
+            return Ok(());
+        }
+        let mut batch = Batch::default();
+        batch.set(&key_chosen, &value)?;
+        Ok(self.persistor.write(batch)?)
+    }
+    async fn learned(&mut self, key: u64) -> Result<Option<String>, Error<LearnerError>> {
+        Ok(self.persistor.get(format!("{}:chosen", key))?)
+    }
+}
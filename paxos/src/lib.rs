@@ -3,6 +3,7 @@
 //! A basic paxos library.
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// Proposal with id and value.
 ///
@@ -13,10 +14,67 @@ pub struct Proposal {
     value: String,
 }
 
+/// Refusal of an acceptor.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AcceptorError {
+    /// The acceptor promised a higher pid.
+    Rejected {
+        /// Highest pid promised.
+        promised: u64,
+    },
+    /// The key is known to be decided.
+    Decided {
+        /// Value chosen for the key.
+        value: String,
+    },
+    /// The key was discarded by truncation.
+    Truncated {
+        /// Watermark below which keys are discarded.
+        below: u64,
+    },
+}
+
+impl fmt::Display for AcceptorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcceptorError::Rejected { promised } => write!(f, "rejected, promised {}", promised),
+            AcceptorError::Decided { value } => write!(f, "decided {:?}", value),
+            AcceptorError::Truncated { below } => write!(f, "truncated below {}", below),
+        }
+    }
+}
+
+/// Refusal of a learner.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LearnerError {
+    /// Another value was learned for the key first, which breaks safety.
+    Conflict {
+        /// Value learned first.
+        learned: String,
+    },
+}
+
+impl fmt::Display for LearnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LearnerError::Conflict { learned } => write!(f, "conflict, learned {:?}", learned),
+        }
+    }
+}
+
 labrpc::service! {
     service acceptor_svc {
+        type Error = AcceptorError;
         fn prepare(key: u64, pid: u64) -> Option<Proposal>;
-        fn accept(key: u64, pid: u64, value: String) -> u64;
+        fn accept(key: u64, pid: u64, value: String) -> ();
+        /// Prepare all keys from `from` onward at once, returning the
+        /// proposals accepted for them.
+        fn prepare_range(from: u64, pid: u64) -> Vec<(u64, Proposal)>;
+        /// Record that `value` was chosen for `key`, so that later prepares
+        /// and accepts of the key are answered with it.
+        fn decide(key: u64, value: String) -> ();
+        /// Discard all keys below `below`, which every replica has applied.
+        fn truncate(below: u64) -> ();
     }
 }
 
@@ -26,6 +84,16 @@ labrpc::service! {
     }
 }
 
+labrpc::service! {
+    service learner_svc {
+        type Error = LearnerError;
+        /// Announce that `value` was chosen for `key`.
+        fn chosen(key: u64, value: String) -> ();
+        /// The value known to be chosen for `key`, if any.
+        fn learned(key: u64) -> Option<String>;
+    }
+}
+
 pub use acceptor_svc::{
     Client as AcceptorClient, Server as AcceptorServer, Service as AcceptorService,
 };
@@ -34,13 +102,21 @@ pub use proposer_svc::{
     Client as ProposerClient, Server as ProposerServer, Service as ProposerService,
 };
 
+pub use learner_svc::{
+    Client as LearnerClient, Server as LearnerServer, Service as LearnerService,
+};
+
 mod acceptor;
+mod learner;
 mod persistor;
 mod proposer;
+mod storage;
 
 /// Util function for testing.
 pub mod tests;
 
 pub use acceptor::Acceptor;
-pub use persistor::Persistor;
+pub use learner::Learner;
+pub use persistor::{Batch, Durability, Persistor};
 pub use proposer::Proposer;
+pub use storage::{LogFile, Memory, Op, RocksDb, Storage};
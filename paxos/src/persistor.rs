@@ -1,31 +1,115 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use labrpc::{
-    anyhow::Result,
+    anyhow::{anyhow, Result},
+    futures::{future::BoxFuture, FutureExt},
     serde::{de::DeserializeOwned, Serialize},
+    tokio::{
+        self,
+        sync::{mpsc, oneshot},
+    },
 };
-use rocksdb::DB;
 
-/// A wrapper of RocksDB which provides set, get, remove operations
+use crate::storage::{Op, RocksDb, Storage};
+
+/// How durable a write to a [`Persistor`] is when it returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Sync each write by itself.
+    Sync,
+    /// Return from writes unsynced, and sync concurrent writes together once
+    /// asked by [`Persistor::sync`].
+    GroupCommit,
+    /// Never sync, so that a crash of the machine may lose any write. Only
+    /// for tests.
+    None,
+}
+
+/// Waiter of a group commit, told its result.
+type Waiter = oneshot::Sender<Result<(), String>>;
+
+/// Number of writes of a group commit so far, and how many of them are
+/// synced.
+#[derive(Default)]
+struct Progress {
+    written: AtomicU64,
+    synced: AtomicU64,
+}
+
+/// Sync `db` for the waiters received, all waiters found queued at once
+/// sharing one sync.
+///
+/// A waiter is queued after its writes, so the sync covers them.
+async fn flush(
+    db: Arc<dyn Storage>,
+    progress: Arc<Progress>,
+    mut waiters: mpsc::UnboundedReceiver<Waiter>,
+) {
+    while let Some(first) = waiters.recv().await {
+        let mut group = vec![first];
+        while let Some(Some(waiter)) = waiters.recv().now_or_never() {
+            group.push(waiter);
+        }
+        let written = progress.written.load(Ordering::SeqCst);
+        let db = db.clone();
+        let result = match tokio::task::spawn_blocking(move || db.sync()).await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if result.is_ok() {
+            progress.synced.fetch_max(written, Ordering::SeqCst);
+        }
+        for waiter in group {
+            let _ = waiter.send(result.clone());
+        }
+    }
+}
+
+/// A wrapper of a [`Storage`] which provides set, get, remove operations
 /// for type that derive [Serialize](serde::Serialize) and [Deserialize](serde::Deserialize)
+///
+/// Writes are as durable as set by [`with_durability`](Self::with_durability),
+/// synced by default.
 pub struct Persistor {
-    db: DB,
+    db: Arc<dyn Storage>,
+    durability: Durability,
+    progress: Arc<Progress>,
+    /// Sender to the task syncing group commits, started by the first one.
+    flusher: Mutex<Option<mpsc::UnboundedSender<Waiter>>>,
 }
 
 impl Persistor {
-    /// Create a new persistor with given path to a file.
+    /// Create a new persistor on RocksDB with given path to a file.
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_storage(RocksDb::open(path).unwrap())
+    }
+    /// Create a new persistor on `storage`.
+    pub fn with_storage<S: Storage + 'static>(storage: S) -> Self {
         Self {
-            db: DB::open_default(path).unwrap(),
+            db: Arc::new(storage),
+            durability: Durability::Sync,
+            progress: Arc::default(),
+            flusher: Mutex::new(None),
         }
     }
+    /// Make writes as durable as `durability`.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
     /// Get a value by given key.
     pub fn get<K, T>(&self, key: K) -> Result<Option<T>>
     where
         K: AsRef<[u8]>,
         T: DeserializeOwned + Clone,
     {
-        let opt = self.db.get(key)?;
+        let opt = self.db.get(key.as_ref())?;
         if let Some(v) = opt {
             Ok(Some(bincode::deserialize(&v)?))
         } else {
@@ -34,13 +118,92 @@ impl Persistor {
     }
     /// Set value associated to given key.
     pub fn set<K: AsRef<[u8]>, T: Serialize>(&self, key: K, value: &T) -> Result<()> {
-        Ok(self.db.put(key, bincode::serialize(value)?)?)
+        let mut batch = Batch::default();
+        batch.set(key, value)?;
+        self.write(batch)
+    }
+    /// Remove the value associated to given key.
+    pub fn delete<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        let mut batch = Batch::default();
+        batch.delete(key);
+        self.write(batch)
+    }
+    /// Apply all writes of `batch` at once.
+    pub fn write(&self, batch: Batch) -> Result<()> {
+        match self.durability {
+            Durability::Sync => self.db.write(batch.ops, true),
+            Durability::GroupCommit => {
+                self.db.write(batch.ops, false)?;
+                self.progress.written.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+            Durability::None => self.db.write(batch.ops, false),
+        }
+    }
+    /// Future resolving once all writes so far are synced, together with
+    /// those of other callers waiting meanwhile, or `None` if all of them
+    /// are synced already.
+    ///
+    /// Only writes of [`Durability::GroupCommit`] need waiting, the others
+    /// are as durable as they will be when they return. Must be called
+    /// within a tokio runtime, which runs the syncs.
+    pub fn sync(&self) -> Option<BoxFuture<'static, Result<()>>> {
+        // A sync that read the count of writes after the caller's write
+        // covers it.
+        let written = self.progress.written.load(Ordering::SeqCst);
+        if self.progress.synced.load(Ordering::SeqCst) >= written {
+            return None;
+        }
+        let (tx, rx) = oneshot::channel();
+        let mut flusher = self.flusher.lock().unwrap();
+        let flusher = flusher.get_or_insert_with(|| {
+            let (flusher, waiters) = mpsc::unbounded_channel();
+            tokio::spawn(flush(self.db.clone(), self.progress.clone(), waiters));
+            flusher
+        });
+        let _ = flusher.send(tx);
+        Some(Box::pin(async move {
+            rx.await
+                .map_err(|_| anyhow!("flusher stopped"))?
+                .map_err(|e| anyhow!(e))
+        }))
+    }
+}
+
+/// Writes to a [`Persistor`] that are applied atomically by
+/// [`Persistor::write`].
+#[derive(Default)]
+pub struct Batch {
+    ops: Vec<Op>,
+}
+
+impl Batch {
+    /// Set value associated to given key.
+    pub fn set<K: AsRef<[u8]>, T: Serialize>(&mut self, key: K, value: &T) -> Result<()> {
+        let value = bincode::serialize(value)?;
+        self.ops.push(Op::Put(key.as_ref().to_vec(), value));
+        Ok(())
+    }
+    /// Remove the value associated to given key.
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.ops.push(Op::Delete(key.as_ref().to_vec()));
+    }
+    /// Remove the values of all keys from `from` inclusive to `to` exclusive,
+    /// in byte order.
+    pub fn delete_range<K: AsRef<[u8]>>(&mut self, from: K, to: K) {
+        self.ops.push(Op::DeleteRange(
+            from.as_ref().to_vec(),
+            to.as_ref().to_vec(),
+        ));
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::Memory;
+    use labrpc::futures::future::join_all;
+    use std::{sync::atomic::AtomicUsize, thread, time::Duration};
 
     fn new_persister() -> Persistor {
         let dir = tempfile::tempdir().unwrap();
@@ -58,4 +221,146 @@ mod test {
         p.set(&key, &value).unwrap();
         assert!(p.get(key).unwrap() == Some(value));
     }
+    #[test]
+    fn test_write() {
+        let p = new_persister();
+
+        let mut batch = Batch::default();
+        batch.set("key1", &1u64).unwrap();
+        batch.set("key2", &String::from("value2")).unwrap();
+        batch.set("key1", &2u64).unwrap();
+        assert!(p.get::<_, u64>("key1").unwrap().is_none());
+
+        p.write(batch).unwrap();
+        assert_eq!(p.get("key1").unwrap(), Some(2u64));
+        assert_eq!(p.get("key2").unwrap(), Some(String::from("value2")));
+    }
+    #[test]
+    fn test_delete() {
+        let p = new_persister();
+
+        for key in &["a", "b1", "b2", "c"] {
+            p.set(key, key).unwrap();
+        }
+        p.delete("a").unwrap();
+        assert!(p.get::<_, String>("a").unwrap().is_none());
+
+        let mut batch = Batch::default();
+        batch.delete_range("b", "c");
+        p.write(batch).unwrap();
+        assert!(p.get::<_, String>("b1").unwrap().is_none());
+        assert!(p.get::<_, String>("b2").unwrap().is_none());
+        assert_eq!(p.get("c").unwrap(), Some(String::from("c")));
+    }
+
+    /// Counts the syncs of a [`Memory`] storage, each taking a while.
+    #[derive(Clone, Default)]
+    struct SlowSync {
+        storage: Memory,
+        syncs: Arc<AtomicUsize>,
+    }
+
+    impl Storage for SlowSync {
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.storage.get(key)
+        }
+        fn write(&self, ops: Vec<Op>, sync: bool) -> Result<()> {
+            if sync {
+                return self.sync().and(self.storage.write(ops, true));
+            }
+            self.storage.write(ops, false)
+        }
+        fn sync(&self) -> Result<()> {
+            self.syncs.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            self.storage.sync()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_durability() {
+        const NWRITE: u64 = 10;
+
+        for &durability in &[Durability::Sync, Durability::GroupCommit, Durability::None] {
+            let storage = SlowSync::default();
+            let p = Persistor::with_storage(storage.clone()).with_durability(durability);
+            let mut synced = Vec::new();
+            for i in 0..NWRITE {
+                p.set(i.to_string(), &i).unwrap();
+                synced.extend(p.sync());
+            }
+            for result in join_all(synced).await {
+                result.unwrap();
+            }
+            assert!(p.sync().is_none());
+
+            // Only synced writes survive.
+            storage.storage.crash();
+            for i in 0..NWRITE {
+                let value = p.get::<_, u64>(i.to_string()).unwrap();
+                if durability == Durability::None {
+                    assert_eq!(value, None);
+                } else {
+                    assert_eq!(value, Some(i));
+                }
+            }
+            // Syncs asked for before the flusher runs share the first one.
+            let syncs = storage.syncs.load(Ordering::SeqCst);
+            match durability {
+                Durability::Sync => assert_eq!(syncs as u64, NWRITE),
+                Durability::GroupCommit => assert_eq!(syncs, 1),
+                Durability::None => assert_eq!(syncs, 0),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_group_commit_interleaved() {
+        let storage = Memory::new();
+        let p = Persistor::with_storage(storage.clone()).with_durability(Durability::GroupCommit);
+
+        // The second caller still waits for its write after the first asked
+        // for a sync.
+        p.set("a", &1u64).unwrap();
+        p.set("b", &2u64).unwrap();
+        let a = p.sync().unwrap();
+        let b = p.sync().unwrap();
+        b.await.unwrap();
+        assert!(storage.durable(b"b").is_some());
+        a.await.unwrap();
+        assert!(storage.durable(b"a").is_some());
+        assert!(p.sync().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_group_commit_concurrent() {
+        const NWRITER: u64 = 8;
+        const NWRITE: u64 = 50;
+
+        let storage = SlowSync::default();
+        let p = Arc::new(
+            Persistor::with_storage(storage.clone()).with_durability(Durability::GroupCommit),
+        );
+        let writers: Vec<_> = (0..NWRITER)
+            .map(|w| {
+                let p = p.clone();
+                let storage = storage.storage.clone();
+                tokio::spawn(async move {
+                    for i in 0..NWRITE {
+                        let key = format!("{}-{}", w, i);
+                        p.set(&key, &i).unwrap();
+                        if let Some(synced) = p.sync() {
+                            synced.await.unwrap();
+                        }
+                        assert!(storage.durable(key.as_bytes()).is_some(), "{}", key);
+                    }
+                })
+            })
+            .collect();
+        for result in join_all(writers).await {
+            result.unwrap();
+        }
+        // Writers waiting together shared syncs.
+        assert!(storage.syncs.load(Ordering::SeqCst) < (NWRITER * NWRITE) as usize);
+    }
 }
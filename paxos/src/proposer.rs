@@ -1,18 +1,33 @@
-use crate::{AcceptorClient, ProposerService};
+use crate::{AcceptorClient, AcceptorError, LearnerClient, Proposal, ProposerService};
 use labrpc::{
-    anyhow::Result,
-    log::{error, trace},
-    tokio,
+    anyhow::{anyhow, Result},
+    futures::{stream::FuturesUnordered, StreamExt},
+    log::{debug, error, trace},
+    tokio, Error,
 };
 use rand::Rng;
-use std::time;
+use std::{collections::HashMap, future::Future, time};
+
+/// Phase 1 won for all keys from `from` onward.
+#[derive(Debug, Clone)]
+struct Leader {
+    pid: u64,
+    from: u64,
+    /// Acceptors that answered the range prepare.
+    acceptors: Vec<usize>,
+    /// Proposals accepted by them or proposed since, by key.
+    accepted: HashMap<u64, Proposal>,
+}
 
 /// Proposer that chooses values by commuicating to acceptors.
 #[derive(Debug, Clone)]
 pub struct Proposer {
     acceptors: Vec<AcceptorClient>,
+    learners: Vec<LearnerClient>,
     id: u32,
     round: u32,
+    multi_paxos: bool,
+    leader: Option<Leader>,
 }
 
 impl Proposer {
@@ -21,55 +36,327 @@ impl Proposer {
         Self {
             id,
             acceptors,
+            learners: Vec::new(),
             round: 0,
+            multi_paxos: false,
+            leader: None,
         }
     }
-}
 
-#[labrpc::async_trait]
-impl ProposerService for Proposer {
-    async fn choose(&mut self, key: u64, value: String) -> Result<String> {
-        loop {
-            self.round += 1;
-            let majority = 1 + self.acceptors.len() / 2;
-            let pid: u64 = ((self.round as u64) << 32) + (self.id as u64);
-
-            let mut prepared = Vec::new();
-            let mut resp = Vec::new();
-
-            for c in self.acceptors.iter() {
-                match c.prepare(key, pid).await {
-                    Ok(opt) => {
-                        prepared.push(c);
-                        if let Some(p) = opt {
-                            resp.push(p)
+    /// Run Multi-Paxos: prepare all keys from the first one chosen onward at
+    /// once, and only send accepts for later keys until preempted.
+    pub fn multi_paxos(mut self) -> Self {
+        self.multi_paxos = true;
+        self
+    }
+
+    /// Announce chosen values to `learners`.
+    pub fn with_learners(mut self, learners: Vec<LearnerClient>) -> Self {
+        self.learners = learners;
+        self
+    }
+
+    /// Tell all acceptors and learners that `value` was chosen for `key`,
+    /// without waiting for them.
+    fn announce(&self, key: u64, value: &str) {
+        for c in self.acceptors.iter() {
+            let c = c.clone();
+            let value = value.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = c.decide(key, value).await {
+                    error!("acceptor error: {}", e);
+                }
+            });
+        }
+        for l in self.learners.iter() {
+            let l = l.clone();
+            let value = value.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = l.chosen(key, value).await {
+                    error!("learner error: {}", e);
+                }
+            });
+        }
+    }
+
+    fn majority(&self) -> usize {
+        1 + self.acceptors.len() / 2
+    }
+
+    fn next_pid(&mut self) -> u64 {
+        self.round += 1;
+        ((self.round as u64) << 32) + (self.id as u64)
+    }
+
+    /// Run both phases for `key`.
+    ///
+    /// Accepts are sent once a majority promised, and to acceptors promising
+    /// later as well, whose accepted proposals no longer matter.
+    async fn round(&mut self, key: u64, value: &str) -> Option<Result<String>> {
+        let majority = self.majority();
+        let max_failed = self.acceptors.len() - majority;
+        let pid = self.next_pid();
+
+        let mut prepares = fan_out(self.acceptors.iter().enumerate(), |c| c.prepare(key, pid));
+        let mut prepared = Vec::new();
+        // Acceptors that failed either phase.
+        let mut failed = 0;
+        let mut latest: Option<Proposal> = None;
+        while prepared.len() < majority {
+            match prepares.next().await? {
+                (i, Ok(opt)) => {
+                    prepared.push(i);
+                    if let Some(p) = opt {
+                        if latest.as_ref().map(|l| l.id) < Some(p.id) {
+                            latest = Some(p);
                         }
                     }
-                    Err(e) => {
-                        error!("choose client error: {}", e);
+                }
+                (_, Err(e)) => {
+                    if let Some(decided) = observe(&mut self.round, e) {
+                        return Some(decided);
+                    }
+                    failed += 1;
+                    if failed > max_failed {
+                        return None;
                     }
                 }
             }
-            if prepared.len() >= majority {
-                let value = {
-                    if let Some(latest) = resp.iter().max_by_key(|p| p.id) {
-                        latest.value.clone()
-                    } else {
-                        value.clone()
+        }
+        let value = latest.map_or_else(|| value.to_string(), |p| p.value);
+
+        let acceptors = &self.acceptors;
+        let accept = |i: usize| {
+            let reply = acceptors[i].accept(key, pid, value.clone());
+            async move { (i, reply.await) }
+        };
+        let mut accepts: FuturesUnordered<_> = prepared.into_iter().map(accept).collect();
+        let mut accepted = 0;
+        while accepted < majority {
+            let reply = tokio::select! {
+                Some((i, reply)) = prepares.next() => match reply {
+                    Ok(_) => {
+                        accepts.push(accept(i));
+                        continue;
+                    }
+                    Err(e) => e,
+                },
+                Some((_, reply)) = accepts.next() => match reply {
+                    Ok(()) => {
+                        accepted += 1;
+                        continue;
                     }
-                };
-
-                let mut accepted = 0;
-                for c in prepared {
-                    if let Ok(aid) = c.accept(key, pid, value.clone()).await {
-                        if (aid == pid) {
-                            accepted += 1;
-                            if accepted >= majority {
-                                return Ok(value);
-                            }
+                    Err(e) => e,
+                },
+                else => return None,
+            };
+            if let Some(decided) = observe(&mut self.round, reply) {
+                return Some(decided);
+            }
+            failed += 1;
+            if failed > max_failed {
+                return None;
+            }
+        }
+        drop(accepts);
+        Some(Ok(value))
+    }
+
+    /// Run phase 1 for all keys from `from` onward.
+    async fn elect(&mut self, from: u64) {
+        let majority = self.majority();
+        let max_failed = self.acceptors.len() - majority;
+        let pid = self.next_pid();
+
+        let mut prepares = fan_out(self.acceptors.iter().enumerate(), |c| {
+            c.prepare_range(from, pid)
+        });
+        let mut acceptors = Vec::new();
+        let mut failed = 0;
+        let mut accepted = HashMap::<u64, Proposal>::new();
+        while acceptors.len() < majority {
+            match prepares.next().await {
+                Some((i, Ok(proposals))) => {
+                    acceptors.push(i);
+                    for (key, p) in proposals {
+                        let latest = accepted.entry(key).or_insert_with(|| p.clone());
+                        if p.id > latest.id {
+                            *latest = p;
                         }
                     }
                 }
+                Some((_, Err(e))) => {
+                    observe(&mut self.round, e);
+                    failed += 1;
+                    if failed > max_failed {
+                        return;
+                    }
+                }
+                None => return,
+            }
+        }
+        debug!(
+            "proposer {} leads from key {} with pid {}",
+            self.id, from, pid
+        );
+        self.leader = Some(Leader {
+            pid,
+            from,
+            acceptors,
+            accepted,
+        });
+    }
+
+    /// Run phase 2 for `key`, after phase 1 if not leading for it.
+    ///
+    /// Acceptors that did not promise during the election are prepared on
+    /// the way. Gives up the lead unless a majority accepts.
+    async fn lead(&mut self, key: u64, value: &str) -> Option<Result<String>> {
+        if self.leader.as_ref().filter(|l| key >= l.from).is_none() {
+            // A lead of later keys says nothing about `key`, and must not
+            // outlive a failed election.
+            self.leader = None;
+            self.elect(key).await;
+        }
+        let majority = self.majority();
+        let max_failed = self.acceptors.len() - majority;
+        let leader = self.leader.as_mut().filter(|l| key >= l.from)?;
+        let pid = leader.pid;
+        let from = leader.from;
+        // Never propose two values for a key under the same pid.
+        let value = leader
+            .accepted
+            .entry(key)
+            .or_insert_with(|| Proposal {
+                id: pid,
+                value: value.to_string(),
+            })
+            .value
+            .clone();
+
+        let promised = &leader.acceptors;
+        let mut accepts: FuturesUnordered<_> = self
+            .acceptors
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let promised = promised.contains(&i);
+                let value = value.clone();
+                async move {
+                    let reply = async {
+                        if !promised {
+                            c.prepare_range(from, pid).await?;
+                        }
+                        c.accept(key, pid, value).await
+                    }
+                    .await;
+                    (i, promised, reply)
+                }
+            })
+            .collect();
+        let mut accepted = Vec::new();
+        let mut failed = 0;
+        while let Some((i, promised, reply)) = accepts.next().await {
+            match reply {
+                Ok(()) => {
+                    accepted.push((i, promised));
+                    if accepted.len() >= majority {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    if let Some(decided) = observe(&mut self.round, e) {
+                        return Some(decided);
+                    }
+                    failed += 1;
+                    if failed > max_failed {
+                        break;
+                    }
+                }
+            }
+        }
+        drop(accepts);
+        if accepted.len() < majority {
+            self.leader = None;
+            return None;
+        }
+        let leader = self.leader.as_mut()?;
+        for (i, promised) in accepted {
+            if !promised {
+                leader.acceptors.push(i);
+            }
+        }
+        Some(Ok(value))
+    }
+
+    /// Let all acceptors discard the keys below `below`, which must already
+    /// be applied by every replica.
+    ///
+    /// Fails unless all acceptors do, so the caller can retry.
+    pub async fn truncate(&self, below: u64) -> Result<()> {
+        let mut replies = fan_out(self.acceptors.iter().enumerate(), |c| c.truncate(below));
+        while let Some((i, reply)) = replies.next().await {
+            reply.map_err(|e| anyhow!("acceptor {} failed to truncate: {}", i, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Log a failed call to an acceptor, raising `round` past the promise of a
+/// rejection so that the next pid exceeds it.
+///
+/// Returns the value of a key the acceptor knows to be decided, or an error
+/// if the key was truncated and can no longer be chosen.
+fn observe(round: &mut u32, e: Error<AcceptorError>) -> Option<Result<String>> {
+    match e {
+        Error::Service(AcceptorError::Rejected { promised }) => {
+            trace!("rejected, promised {}", promised);
+            *round = (*round).max((promised >> 32) as u32);
+        }
+        Error::Service(AcceptorError::Decided { value }) => return Some(Ok(value)),
+        Error::Service(AcceptorError::Truncated { below }) => {
+            return Some(Err(anyhow!("key truncated below {}", below)))
+        }
+        e => error!("acceptor error: {}", e),
+    }
+    None
+}
+
+/// Call all `acceptors` at once by `f`, yielding the replies with the index
+/// of the acceptor as they arrive.
+///
+/// Dropping the stream abandons calls still in flight.
+fn fan_out<'a, I, F, Fut, T>(
+    acceptors: I,
+    f: F,
+) -> FuturesUnordered<impl Future<Output = (usize, T)>>
+where
+    I: IntoIterator<Item = (usize, &'a AcceptorClient)>,
+    F: Fn(&'a AcceptorClient) -> Fut,
+    Fut: Future<Output = T>,
+{
+    acceptors
+        .into_iter()
+        .map(|(i, c)| {
+            let reply = f(c);
+            async move { (i, reply.await) }
+        })
+        .collect()
+}
+
+#[labrpc::async_trait]
+impl ProposerService for Proposer {
+    async fn choose(&mut self, key: u64, value: String) -> Result<String> {
+        loop {
+            let chosen = if self.multi_paxos {
+                self.lead(key, &value).await
+            } else {
+                self.round(key, &value).await
+            };
+            if let Some(value) = chosen {
+                let value = value?;
+                self.announce(key, &value);
+                return Ok(value);
             }
             let dt: u64 = rand::thread_rng().gen_range(10..2000);
             tokio::time::sleep(time::Duration::from_millis(dbg!(dt))).await;
@@ -0,0 +1,313 @@
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use labrpc::anyhow::Result;
+use rocksdb::{WriteBatch, WriteOptions, DB};
+use serde::{Deserialize, Serialize};
+
+/// A write to a [`Storage`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Op {
+    /// Set the value of a key.
+    Put(Vec<u8>, Vec<u8>),
+    /// Remove the value of a key.
+    Delete(Vec<u8>),
+    /// Remove the values of all keys from the first inclusive to the second
+    /// exclusive, in byte order.
+    DeleteRange(Vec<u8>, Vec<u8>),
+}
+
+/// Key-value store backing a [`Persistor`](crate::Persistor).
+pub trait Storage: Send + Sync {
+    /// Get the value of `key`.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// Apply all `ops` at once, durable before returning if `sync`.
+    fn write(&self, ops: Vec<Op>, sync: bool) -> Result<()>;
+    /// Make all writes so far durable.
+    fn sync(&self) -> Result<()>;
+}
+
+/// Storage in a RocksDB database.
+pub struct RocksDb {
+    db: DB,
+}
+
+impl RocksDb {
+    /// Open or create the database at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            db: DB::open_default(path)?,
+        })
+    }
+}
+
+impl Storage for RocksDb {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?)
+    }
+    fn write(&self, ops: Vec<Op>, sync: bool) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for op in ops {
+            match op {
+                Op::Put(key, value) => batch.put(key, value),
+                Op::Delete(key) => batch.delete(key),
+                Op::DeleteRange(from, to) => batch.delete_range(from, to),
+            }
+        }
+        let mut opts = WriteOptions::default();
+        opts.set_sync(sync);
+        Ok(self.db.write_opt(batch, &opts)?)
+    }
+    fn sync(&self) -> Result<()> {
+        // A synced write syncs the log of all writes before it as well.
+        self.write(Vec::new(), true)
+    }
+}
+
+type Map = BTreeMap<Vec<u8>, Vec<u8>>;
+
+fn apply(map: &mut Map, op: &Op) {
+    match op {
+        Op::Put(key, value) => {
+            map.insert(key.clone(), value.clone());
+        }
+        Op::Delete(key) => {
+            map.remove(key);
+        }
+        Op::DeleteRange(from, to) => {
+            let keys: Vec<_> = map
+                .range(from.clone()..to.clone())
+                .map(|(k, _)| k.clone())
+                .collect();
+            for key in keys {
+                map.remove(&key);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct MemoryState {
+    data: Map,
+    /// What survives a crash.
+    durable: Map,
+    /// Writes since the last synced one, lost by a crash.
+    unsynced: Vec<Op>,
+}
+
+/// Storage in memory, for tests.
+///
+/// Clones share the same data, so that an acceptor restarted on a clone finds
+/// what survived [`crash`](Memory::crash).
+#[derive(Clone, Default)]
+pub struct Memory {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl Memory {
+    /// Create an empty storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lose all writes not synced yet, as a crash of the machine would.
+    ///
+    /// A synced write also makes the unsynced writes before it durable.
+    pub fn crash(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.data = state.durable.clone();
+        state.unsynced.clear();
+    }
+
+    /// The value of `key` that would survive a crash.
+    #[cfg(test)]
+    pub(crate) fn durable(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().durable.get(key).cloned()
+    }
+}
+
+impl Storage for Memory {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.state.lock().unwrap().data.get(key).cloned())
+    }
+    fn write(&self, ops: Vec<Op>, sync: bool) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        for op in ops.iter() {
+            apply(&mut state.data, op);
+        }
+        state.unsynced.extend(ops);
+        if sync {
+            for op in state.unsynced.drain(..) {
+                apply(&mut state.durable, &op);
+            }
+        }
+        Ok(())
+    }
+    fn sync(&self) -> Result<()> {
+        self.write(Vec::new(), true)
+    }
+}
+
+/// Storage in a file that writes are appended to, replayed into memory when
+/// opened.
+///
+/// Each write is a record of its length as 4 bytes little-endian followed by
+/// its ops in bincode. The file is never compacted, so deleting does not
+/// reclaim space.
+pub struct LogFile {
+    state: Mutex<(File, Map)>,
+}
+
+impl LogFile {
+    /// Open or create the log at `path`.
+    ///
+    /// A record torn by a crash at the end of the log is discarded.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        let mut data = Map::new();
+        let mut valid = 0;
+        while buf.len() - valid >= 4 {
+            let mut len = [0; 4];
+            len.copy_from_slice(&buf[valid..valid + 4]);
+            let end = valid + 4 + u32::from_le_bytes(len) as usize;
+            if end > buf.len() {
+                break;
+            }
+            let ops: Vec<Op> = match bincode::deserialize(&buf[valid + 4..end]) {
+                Ok(ops) => ops,
+                Err(_) => break,
+            };
+            for op in ops.iter() {
+                apply(&mut data, op);
+            }
+            valid = end;
+        }
+        // Appends must follow the last complete record.
+        file.set_len(valid as u64)?;
+        Ok(Self {
+            state: Mutex::new((file, data)),
+        })
+    }
+}
+
+impl Storage for LogFile {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.state.lock().unwrap().1.get(key).cloned())
+    }
+    fn write(&self, ops: Vec<Op>, sync: bool) -> Result<()> {
+        let ops_buf = bincode::serialize(&ops)?;
+        let mut record = u32::try_from(ops_buf.len())?.to_le_bytes().to_vec();
+        record.extend(ops_buf);
+
+        let mut state = self.state.lock().unwrap();
+        let (file, data) = &mut *state;
+        file.write_all(&record)?;
+        if sync {
+            file.sync_data()?;
+        }
+        for op in ops.iter() {
+            apply(data, op);
+        }
+        Ok(())
+    }
+    fn sync(&self) -> Result<()> {
+        Ok(self.state.lock().unwrap().0.sync_data()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn put(key: &str, value: &str) -> Op {
+        Op::Put(key.into(), value.into())
+    }
+
+    fn get(s: &dyn Storage, key: &str) -> Option<String> {
+        s.get(key.as_bytes())
+            .unwrap()
+            .map(|v| String::from_utf8(v).unwrap())
+    }
+
+    fn check_ops(s: &dyn Storage) {
+        s.write(vec![put("a", "1"), put("b1", "2"), put("b2", "3")], false)
+            .unwrap();
+        s.write(vec![put("c", "4"), Op::Delete("a".into())], true)
+            .unwrap();
+        assert_eq!(get(s, "a"), None);
+        assert_eq!(get(s, "b1"), Some("2".to_string()));
+
+        s.write(vec![Op::DeleteRange("b".into(), "c".into())], true)
+            .unwrap();
+        assert_eq!(get(s, "b1"), None);
+        assert_eq!(get(s, "b2"), None);
+        assert_eq!(get(s, "c"), Some("4".to_string()));
+    }
+
+    #[test]
+    fn test_ops() {
+        let dir = tempfile::tempdir().unwrap();
+        check_ops(&RocksDb::open(dir.path().join("rocksdb")).unwrap());
+        check_ops(&Memory::new());
+        check_ops(&LogFile::open(dir.path().join("log")).unwrap());
+    }
+
+    #[test]
+    fn test_memory_crash() {
+        let s = Memory::new();
+        s.write(vec![put("a", "1")], false).unwrap();
+        s.write(vec![put("b", "2")], true).unwrap();
+        s.write(vec![put("c", "3"), put("a", "4")], false).unwrap();
+        assert_eq!(get(&s, "c"), Some("3".to_string()));
+
+        s.clone().crash();
+        assert_eq!(get(&s, "a"), Some("1".to_string()));
+        assert_eq!(get(&s, "b"), Some("2".to_string()));
+        assert_eq!(get(&s, "c"), None);
+        s.write(vec![put("c", "5")], false).unwrap();
+        s.sync().unwrap();
+        s.crash();
+        assert_eq!(get(&s, "c"), Some("5".to_string()));
+    }
+
+    #[test]
+    fn test_log_file_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        {
+            let s = LogFile::open(&path).unwrap();
+            s.write(vec![put("a", "1"), put("b", "2")], true).unwrap();
+            s.write(vec![Op::Delete("a".into())], false).unwrap();
+        }
+        // Tear the last record.
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let s = LogFile::open(&path).unwrap();
+        assert_eq!(get(&s, "a"), Some("1".to_string()));
+        s.write(vec![put("c", "3")], true).unwrap();
+        drop(s);
+        let s = LogFile::open(&path).unwrap();
+        assert_eq!(get(&s, "b"), Some("2".to_string()));
+        assert_eq!(get(&s, "c"), Some("3".to_string()));
+    }
+}
@@ -1,12 +1,41 @@
-use crate::{Acceptor, AcceptorClient, AcceptorServer, Proposer, ProposerService};
+use crate::{
+    Acceptor, AcceptorClient, AcceptorError, AcceptorServer, Durability, Learner, LearnerClient,
+    LearnerError, LearnerServer, Memory, Op, Proposal, Proposer, ProposerService, Storage,
+};
 
-use labrpc::{log::info, tokio, tokio::sync::mpsc, Network};
+use labrpc::{
+    history::Message,
+    log::info,
+    nemesis::{self, Nemesis, SeedGuard},
+    tokio,
+    tokio::sync::mpsc,
+    tokio::time::Instant,
+    Error, Network,
+};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
 use std::{convert::TryFrom, path::Path};
 use tokio::task::JoinHandle;
 
+/// Environment variable of the duration of chaos tests in seconds.
+pub const CHAOS_ENV: &str = "CHAOS_SECS";
+
+/// Duration of chaos tests, two minutes unless set by [`CHAOS_ENV`].
+pub fn chaos_duration() -> Duration {
+    let secs = std::env::var(CHAOS_ENV)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(120);
+    Duration::from_secs(secs)
+}
+
 /// Create random string of length n.
 pub fn random_string(n: usize) -> String {
     let v = rand::thread_rng()
@@ -16,12 +45,34 @@ pub fn random_string(n: usize) -> String {
     String::from_utf8(v).expect("found invalid UTF-8")
 }
 
+/// Call an acceptor by `f` until the call does not fail by a random error.
+pub async fn retry<T, F, Fut>(mut f: F) -> Result<T, Error<AcceptorError>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error<AcceptorError>>>,
+{
+    loop {
+        match f().await {
+            Err(Error::Transport(_)) => continue,
+            reply => return reply,
+        }
+    }
+}
+
 /// Create a cluster of acceptors for testing.
 pub fn acceptor_cluster(
     dir: &Path,
     n: u32,
 ) -> (Vec<AcceptorClient>, Vec<JoinHandle<()>>, JoinHandle<()>) {
-    let mut net = Network::new();
+    acceptor_cluster_on(Network::new(), dir, n)
+}
+
+/// Create a cluster of acceptors named `acc-<i>` on a given network.
+pub fn acceptor_cluster_on(
+    mut net: Network,
+    dir: &Path,
+    n: u32,
+) -> (Vec<AcceptorClient>, Vec<JoinHandle<()>>, JoinHandle<()>) {
     let mut clients = Vec::new();
     let mut servers = Vec::new();
 
@@ -33,23 +84,41 @@ pub fn acceptor_cluster(
 
         let p = dir.join(&id);
 
+        // Restarted acceptors reopen the database of the same process, which
+        // keeps unsynced writes.
         let (client, server_routine) = net
             .register_service::<AcceptorServer<Acceptor>, _, _, _>(id, move || {
-                Acceptor::new(p.clone())
+                Acceptor::new(p.clone()).with_durability(Durability::None)
             });
         clients.push(client);
         servers.push(tokio::spawn(server_routine));
     }
 
-    // Wait until servers finish registration.
-    let nodes = net.nodes.clone();
+    let net_thread = tokio::spawn(async move {
+        net.run().await;
+    });
+
+    (clients, servers, net_thread)
+}
+
+/// Create a cluster of learners named `learner-<i>` on a given network.
+pub fn learner_cluster_on(
+    mut net: Network,
+    dir: &Path,
+    n: u32,
+) -> (Vec<LearnerClient>, Vec<JoinHandle<()>>, JoinHandle<()>) {
+    let mut clients = Vec::new();
+    let mut servers = Vec::new();
+
     for i in 0..n {
-        let id = server_id(i);
-        loop {
-            if nodes.clone().lock().unwrap().get(&id).is_some() {
-                break;
-            }
-        }
+        let id = format!("learner-{}", i);
+        let p = dir.join(&id);
+        let (client, server_routine) = net
+            .register_service::<LearnerServer<Learner>, _, _, _>(id, move || {
+                Learner::new(p.clone())
+            });
+        clients.push(client);
+        servers.push(tokio::spawn(server_routine));
     }
 
     let net_thread = tokio::spawn(async move {
@@ -61,16 +130,33 @@ pub fn acceptor_cluster(
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 20)]
 async fn test_single_key() {
+    check_single_key().await;
+}
+
+/// Runs on a paused clock, which jumps to the next timer whenever all tasks
+/// wait, so that backoff of proposers takes no wall time.
+#[tokio::test]
+async fn test_single_key_paused() {
+    tokio::time::pause();
+    check_single_key().await;
+}
+
+/// Let competing proposers choose a value of the same key.
+#[cfg(test)]
+async fn check_single_key() {
     const KEY: u64 = 1;
     const N: u32 = 10;
     const NPROP: u32 = 10;
 
-    env_logger::init();
+    let _ = env_logger::try_init();
+    let _trace = labrpc::trace::init_from_env();
     let dir = tempfile::TempDir::new().unwrap();
 
     let mut proposers = Vec::new();
 
-    let (acc_clients, acceptors, net_thread) = acceptor_cluster(dir.path(), N);
+    let net = Network::new();
+    let history = net.record();
+    let (acc_clients, acceptors, net_thread) = acceptor_cluster_on(net, dir.path(), N);
 
     let (tx, mut rx) = mpsc::channel(usize::try_from(2 * N).unwrap());
 
@@ -95,4 +181,532 @@ async fn test_single_key() {
             s = Some(t);
         }
     }
+
+    // An acceptor is only asked to accept a proposal it was asked to
+    // promise, and all accepts of a proposal carry the same value.
+    let messages = history.messages();
+    let mut values = HashMap::new();
+    for m in messages.iter().filter(|m| m.method() == Some("accept")) {
+        let pid = m.arg("pid").unwrap();
+        assert!(
+            messages[..m.seq]
+                .iter()
+                .any(|p| p.method() == Some("prepare")
+                    && p.to == m.to
+                    && p.arg("pid") == Some(pid)),
+            "{} accepted pid {} before prepare",
+            m.to,
+            pid
+        );
+        let value = m.arg("value").unwrap();
+        assert_eq!(
+            values.entry(pid.clone()).or_insert(value),
+            &value,
+            "pid {}",
+            pid
+        );
+    }
+    assert!(!values.is_empty());
+}
+
+/// A rejected proposer skips the rounds below the promise it was told about.
+#[tokio::test]
+async fn test_rejection() {
+    const N: u32 = 3;
+    const KEY: u64 = 1;
+    const PROMISED: u64 = 5 << 32;
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let net = Network::new();
+    let history = net.record();
+    let (acc_clients, _acceptors, _net_thread) = acceptor_cluster_on(net, dir.path(), N);
+
+    for c in acc_clients.iter() {
+        while c.prepare(KEY, PROMISED).await.is_err() {}
+        match retry(|| c.prepare(KEY, PROMISED - 1)).await {
+            Err(Error::Service(AcceptorError::Rejected { promised })) => {
+                assert_eq!(promised, PROMISED)
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    let mut p = Proposer::new(0, acc_clients);
+    assert_eq!(p.choose(KEY, "v".to_string()).await.unwrap(), "v");
+
+    let pid = |m: &Message| m.arg("pid").and_then(|pid| pid.as_u64()).unwrap();
+    let prepared: Vec<_> = history
+        .filter(|m| m.method() == Some("prepare"))
+        .iter()
+        .map(pid)
+        .filter(|&pid| pid < PROMISED - 1)
+        .collect();
+    assert!(prepared.iter().all(|&pid| pid == 1 << 32), "{:?}", prepared);
+    assert!(history
+        .filter(|m| m.method() == Some("accept"))
+        .iter()
+        .all(|m| pid(m) > PROMISED));
+}
+
+/// Accepts may arrive before their prepare, at an acceptor that lost its
+/// state or by reordering, and any message may arrive twice.
+#[tokio::test]
+async fn test_accept_reordered_and_duplicated() {
+    const KEY: u64 = 1;
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let (acc_clients, _acceptors, _net_thread) = acceptor_cluster(dir.path(), 1);
+    let c = &acc_clients[0];
+    let accepted = |p: Option<Proposal>| p.map(|p| (p.id, p.value));
+    let rejected = |reply| match reply {
+        Err(Error::Service(AcceptorError::Rejected { promised })) => promised,
+        _ => panic!("not rejected"),
+    };
+
+    // Accept before prepare, twice.
+    for _ in 0..2 {
+        retry(|| c.accept(KEY, 2 << 32, "a".to_string()))
+            .await
+            .unwrap();
+    }
+    // The prepare of the accepted proposal arrives late, twice.
+    for _ in 0..2 {
+        let p = retry(|| c.prepare(KEY, 2 << 32)).await.unwrap();
+        assert_eq!(accepted(p), Some((2 << 32, "a".to_string())));
+    }
+
+    // Older proposals are rejected by the promise of the accept.
+    let reply = retry(|| c.accept(KEY, 1 << 32, "b".to_string())).await;
+    assert_eq!(rejected(reply), 2 << 32);
+    let reply = retry(|| c.prepare(KEY, 1 << 32)).await.map(|_| ());
+    assert_eq!(rejected(reply), 2 << 32);
+
+    // A newer accept without prepare replaces the accepted proposal and
+    // raises the promise.
+    retry(|| c.accept(KEY, 3 << 32, "c".to_string()))
+        .await
+        .unwrap();
+    let reply = retry(|| c.accept(KEY, 2 << 32, "a".to_string())).await;
+    assert_eq!(rejected(reply), 3 << 32);
+    let p = retry(|| c.prepare(KEY, 4 << 32)).await.unwrap();
+    assert_eq!(accepted(p), Some((3 << 32, "c".to_string())));
+
+    // Accepts of other keys only need to beat the range promise.
+    retry(|| c.prepare_range(KEY + 1, 5 << 32)).await.unwrap();
+    let reply = retry(|| c.accept(KEY + 1, 4 << 32, "d".to_string())).await;
+    assert_eq!(rejected(reply), 5 << 32);
+    retry(|| c.accept(KEY + 2, 5 << 32, "e".to_string()))
+        .await
+        .unwrap();
+}
+
+/// Promises and accepted proposals are synced before the reply, so they
+/// survive an acceptor crash that loses unsynced writes.
+#[tokio::test]
+async fn test_acceptor_crash() {
+    const KEY: u64 = 1;
+    const ID: &str = "acc-0";
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+
+    for &durability in &[Durability::Sync, Durability::GroupCommit] {
+        let mut net = Network::new();
+        let faults = net.faults();
+        let storage = Memory::new();
+        let (c, server) = net.register_service::<AcceptorServer<Acceptor>, AcceptorClient, _, _>(
+            ID.to_string(),
+            move || {
+                storage.crash();
+                Acceptor::with_storage(storage.clone()).with_durability(durability)
+            },
+        );
+        tokio::spawn(server);
+        tokio::spawn(async move { net.run().await });
+
+        retry(|| c.prepare(KEY, 2 << 32)).await.unwrap();
+        retry(|| c.accept(KEY + 1, 3 << 32, "a".to_string()))
+            .await
+            .unwrap();
+        faults.crash(ID);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        faults.restart(ID);
+
+        match retry(|| c.prepare(KEY, 1 << 32)).await {
+            Err(Error::Service(AcceptorError::Rejected { promised })) => {
+                assert_eq!(promised, 2 << 32)
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+        let p = retry(|| c.prepare(KEY + 1, 4 << 32))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((p.id, p.value), (3 << 32, "a".to_string()));
+    }
+}
+
+/// Counts the syncs of a [`Memory`] storage, each taking a while.
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct SlowSync {
+    storage: Memory,
+    syncs: Arc<AtomicUsize>,
+}
+
+#[cfg(test)]
+impl Storage for SlowSync {
+    fn get(&self, key: &[u8]) -> labrpc::anyhow::Result<Option<Vec<u8>>> {
+        self.storage.get(key)
+    }
+    fn write(&self, ops: Vec<Op>, sync: bool) -> labrpc::anyhow::Result<()> {
+        if sync {
+            return self.sync().and(self.storage.write(ops, true));
+        }
+        self.storage.write(ops, false)
+    }
+    fn sync(&self) -> labrpc::anyhow::Result<()> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(20));
+        self.storage.sync()
+    }
+}
+
+/// Concurrent accepts to an acceptor committing in groups share syncs, and
+/// are synced once replied.
+#[tokio::test]
+async fn test_group_commit() {
+    const NACCEPT: u64 = 20;
+
+    let _ = env_logger::try_init();
+
+    let mut net = Network::new();
+    let storage = SlowSync::default();
+    let (c, server) = {
+        let storage = storage.clone();
+        net.register_service::<AcceptorServer<Acceptor>, AcceptorClient, _, _>(
+            "acc-0".to_string(),
+            move || {
+                Acceptor::with_storage(storage.clone()).with_durability(Durability::GroupCommit)
+            },
+        )
+    };
+    tokio::spawn(server);
+    tokio::spawn(async move { net.run().await });
+
+    let accepts: Vec<_> = (0..NACCEPT)
+        .map(|key| {
+            let c = c.clone();
+            tokio::spawn(async move { retry(|| c.accept(key, 1, key.to_string())).await })
+        })
+        .collect();
+    for accept in accepts {
+        accept.await.unwrap().unwrap();
+    }
+    let syncs = storage.syncs.load(Ordering::SeqCst);
+    assert!(syncs <= NACCEPT as usize / 2, "{} syncs", syncs);
+
+    storage.storage.crash();
+    for key in 0..NACCEPT {
+        let p = retry(|| c.prepare(key, 2)).await.unwrap().unwrap();
+        assert_eq!(p.value, key.to_string());
+    }
+}
+
+/// Learners learn the value chosen by competing proposers.
+#[tokio::test]
+async fn test_learner() {
+    const KEY: u64 = 1;
+    const N: u32 = 3;
+    const NPROP: u32 = 3;
+    const NLEARNER: u32 = 2;
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let (acc_clients, _acceptors, _acc_net) = acceptor_cluster(dir.path(), N);
+    let (learners, _learner_servers, _learner_net) =
+        learner_cluster_on(Network::new(), dir.path(), NLEARNER);
+
+    let proposers: Vec<_> = (0..NPROP)
+        .map(|i| {
+            let mut p = Proposer::new(i, acc_clients.clone()).with_learners(learners.clone());
+            tokio::spawn(async move { p.choose(KEY, format!("p[{}]", i)).await.unwrap() })
+        })
+        .collect();
+    let mut chosen = None;
+    for p in proposers {
+        let v = p.await.unwrap();
+        assert_eq!(chosen.get_or_insert_with(|| v.clone()), &v);
+    }
+
+    for l in learners.iter() {
+        // Announcements are sent in the background.
+        while l.learned(KEY).await.unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(l.learned(KEY).await.unwrap(), chosen);
+        assert_eq!(l.learned(KEY + 1).await.unwrap(), None);
+    }
+}
+
+/// A learner told of conflicting values for a key refuses the later one and
+/// keeps serving.
+#[tokio::test]
+async fn test_learner_conflict() {
+    const KEY: u64 = 1;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let (learners, _servers, _net) = learner_cluster_on(Network::new(), dir.path(), 1);
+    let l = &learners[0];
+
+    l.chosen(KEY, "a".to_string()).await.unwrap();
+    l.chosen(KEY, "a".to_string()).await.unwrap();
+    // Refused with a declared error, not by crashing the server.
+    match l.chosen(KEY, "b".to_string()).await {
+        Err(Error::Service(LearnerError::Conflict { learned })) => assert_eq!(learned, "a"),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+    assert_eq!(l.learned(KEY).await.unwrap(), Some("a".to_string()));
+}
+
+/// Proposers learn the value of a decided key from the first prepare.
+#[tokio::test]
+async fn test_decided() {
+    const KEY: u64 = 1;
+    const N: u32 = 5;
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let net = Network::new();
+    let history = net.record();
+    let (acc_clients, _acceptors, _net_thread) = acceptor_cluster_on(net, dir.path(), N);
+
+    let mut p = Proposer::new(0, acc_clients.clone());
+    let chosen = p.choose(KEY, "a".to_string()).await.unwrap();
+    // Decisions are announced in the background.
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(history.count(|m| m.method() == Some("decide")), N as usize);
+
+    history.clear();
+    let mut p = Proposer::new(1, acc_clients.clone());
+    assert_eq!(p.choose(KEY, "b".to_string()).await.unwrap(), chosen);
+    assert_eq!(history.count(|m| m.method() == Some("accept")), 0);
+
+    // A Multi-Paxos leader learns it from accepts after its election.
+    let mut p = Proposer::new(2, acc_clients).multi_paxos();
+    assert_eq!(p.choose(KEY, "c".to_string()).await.unwrap(), chosen);
+}
+
+/// Acceptors discard the keys below the watermark and refuse them since,
+/// keeping the keys above.
+#[tokio::test]
+async fn test_truncate() {
+    const N: u32 = 3;
+    const NKEY: u64 = 10;
+    const BELOW: u64 = 6;
+    const PID: u64 = 100 << 32;
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let (acc_clients, _acceptors, _net_thread) = acceptor_cluster(dir.path(), N);
+    let mut p = Proposer::new(0, acc_clients.clone()).multi_paxos();
+    for key in 0..NKEY {
+        p.choose(key, format!("v{}", key)).await.unwrap();
+    }
+    p.truncate(BELOW).await.unwrap();
+    // Truncating is idempotent and never moves the watermark back.
+    p.truncate(BELOW - 1).await.unwrap();
+
+    for c in acc_clients.iter() {
+        match retry(|| c.prepare(BELOW - 1, PID)).await {
+            Err(Error::Service(AcceptorError::Truncated { below })) => assert_eq!(below, BELOW),
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+        let keys: Vec<_> = retry(|| c.prepare_range(0, PID))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert!(keys.iter().all(|&key| key >= BELOW), "{:?}", keys);
+    }
+
+    assert!(p.choose(0, "a".to_string()).await.is_err());
+    let value = format!("v{}", NKEY);
+    assert_eq!(p.choose(NKEY, value.clone()).await.unwrap(), value);
+}
+
+/// A Multi-Paxos leader only runs phase 1 again when a majority fails to
+/// accept, which random errors of acceptors make rare.
+#[tokio::test]
+async fn test_multi_paxos_leader() {
+    const N: u32 = 10;
+    const NKEY: u64 = 50;
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let net = Network::new();
+    let history = net.record();
+    let (acc_clients, _acceptors, _net_thread) = acceptor_cluster_on(net, dir.path(), N);
+
+    let mut p = Proposer::new(0, acc_clients).multi_paxos();
+    for key in 0..NKEY {
+        let value = format!("v{}", key);
+        assert_eq!(p.choose(key, value.clone()).await.unwrap(), value);
+    }
+
+    assert_eq!(history.count(|m| m.method() == Some("prepare")), 0);
+    let elections: HashSet<_> = history
+        .filter(|m| m.method() == Some("prepare_range"))
+        .into_iter()
+        .map(|m| m.arg("pid").cloned())
+        .collect();
+    let elections = elections.len();
+    assert!(elections >= 1);
+    assert!(elections < NKEY as usize / 5, "{} elections", elections);
+}
+
+/// Multi-Paxos leaders preempting each other choose the same values.
+#[tokio::test]
+async fn test_multi_paxos_dueling() {
+    const N: u32 = 5;
+    const NPROP: u32 = 3;
+    const NKEY: u64 = 20;
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let (acc_clients, _acceptors, _net_thread) = acceptor_cluster(dir.path(), N);
+
+    let proposers: Vec<_> = (0..NPROP)
+        .map(|i| {
+            let mut p = Proposer::new(i, acc_clients.clone()).multi_paxos();
+            tokio::spawn(async move {
+                let mut chosen = Vec::new();
+                for key in 0..NKEY {
+                    chosen.push(p.choose(key, format!("p[{}]", i)).await.unwrap());
+                }
+                chosen
+            })
+        })
+        .collect();
+    let mut chosen = None;
+    for p in proposers {
+        let v = p.await.unwrap();
+        assert_eq!(chosen.get_or_insert_with(|| v.clone()), &v);
+    }
+}
+
+/// A leader of later keys that fails to be elected for an earlier key does
+/// not send accepts for it under its old pid, which was never prepared for
+/// the key.
+#[tokio::test]
+async fn test_multi_paxos_failed_election() {
+    const N: u32 = 3;
+    const KEY: u64 = 3;
+    const RIVAL: u64 = 100 << 32;
+
+    tokio::time::pause();
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let net = Network::new();
+    let history = net.record();
+    let (acc_clients, _acceptors, _net_thread) = acceptor_cluster_on(net, dir.path(), N);
+
+    let mut p = Proposer::new(0, acc_clients.clone()).multi_paxos();
+    assert_eq!(p.choose(KEY + 2, "a".to_string()).await.unwrap(), "a");
+    let old_pid = history
+        .filter(|m| m.method() == Some("prepare_range"))
+        .last()
+        .and_then(|m| m.arg("pid").and_then(|pid| pid.as_u64()))
+        .unwrap();
+
+    // A rival promise of later keys makes the election for `KEY` fail,
+    // leaving `KEY` itself unpromised.
+    for c in acc_clients.iter() {
+        retry(|| c.prepare_range(KEY + 10, RIVAL)).await.unwrap();
+    }
+    assert_eq!(p.choose(KEY, "b".to_string()).await.unwrap(), "b");
+
+    let accepts = history.filter(|m| {
+        m.method() == Some("accept") && m.arg("key").and_then(|k| k.as_u64()) == Some(KEY)
+    });
+    assert!(!accepts.is_empty());
+    for m in accepts {
+        let pid = m.arg("pid").and_then(|pid| pid.as_u64()).unwrap();
+        assert!(pid > RIVAL, "accept of key {} under pid {}", KEY, pid);
+        assert_ne!(pid, old_pid);
+    }
+}
+
+/// Choose values of many keys while acceptors crash, restart and lose messages.
+///
+/// Runs for [`chaos_duration`], thus ignored by default.
+#[tokio::test(flavor = "multi_thread", worker_threads = 20)]
+#[ignore]
+async fn test_chaos() {
+    const N: u32 = 5;
+    const NPROP: u32 = 3;
+
+    let _ = env_logger::try_init();
+    let seed = nemesis::seed_from_env();
+    let _seed = SeedGuard(seed);
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let net = Network::new();
+    let faults = net.faults();
+    let (acc_clients, _acceptors, _net_thread) = acceptor_cluster_on(net, dir.path(), N);
+
+    let duration = chaos_duration();
+    let config = nemesis::Config {
+        max_crashed: (N as usize - 1) / 2,
+        ..nemesis::Config::default()
+    };
+    let mut nemesis = Nemesis::new(seed, config).target(
+        faults.clone(),
+        (0..N).map(|i| format!("acc-{}", i)).collect(),
+    );
+    let chaos = tokio::spawn(async move { nemesis.run(duration).await });
+
+    // On the clock of tokio, like the nemesis.
+    let start = Instant::now();
+    let mut key = 0;
+    let mut faulty = 0;
+    while start.elapsed() < duration {
+        if !faults.crashed().is_empty() {
+            faulty += 1;
+        }
+        let proposers: Vec<_> = (0..NPROP)
+            .map(|i| {
+                let mut p = Proposer::new(i, acc_clients.clone());
+                tokio::spawn(async move { p.choose(key, format!("p[{}]", i)).await.unwrap() })
+            })
+            .collect();
+        let mut chosen = None;
+        for p in proposers {
+            let v = p.await.unwrap();
+            assert_eq!(chosen.get_or_insert_with(|| v.clone()), &v, "key {}", key);
+        }
+        info!("key {} chose {:?}", key, chosen);
+        key += 1;
+    }
+    chaos.await.unwrap();
+    // The nemesis acted while keys were chosen, not only before.
+    assert!(faulty > 0, "no key of {} chosen with an acceptor down", key);
 }
@@ -3,13 +3,16 @@ use criterion::Criterion;
 use criterion::{criterion_group, criterion_main};
 
 use labrpc::{
+    failover::{Failover, Policy},
     futures::executor::block_on,
+    link::Link,
     tokio::{self, runtime::Builder, task, time::Instant},
+    Network,
 };
 
-use std::{convert::TryFrom, path::Path};
+use std::{convert::TryFrom, path::Path, time::Duration};
 
-use paxos::tests::acceptor_cluster;
+use paxos::tests::{acceptor_cluster, acceptor_cluster_on};
 use paxoskv::{kv::ClusterInfo, tests::kv_cluster};
 
 fn bench_set(c: &mut Criterion) {
@@ -47,26 +50,20 @@ fn bench_set(c: &mut Criterion) {
                     }
                 }
                 println!("start iters: {}, #node: {}, #query: {}", iters, N, NQUERIES);
+                let kv = Failover::new(kv_clients).policy(Policy::Leader);
 
                 let start = Instant::now();
 
                 for _ in 0..iters {
                     for i in 0..NQUERIES {
-                        let clients = kv_clients.clone();
+                        let kv = kv.clone();
                         setter.push(tokio::spawn(async move {
                             let cmd_id = u64::try_from(i).unwrap();
-                            loop {
-                                let mut finish = false;
-                                for c in clients.iter() {
-                                    if c.set(cmd_id, get_key(i), get_value(i)).await.is_ok() {
-                                        finish = true;
-                                        break;
-                                    }
-                                }
-                                if finish {
-                                    break;
-                                }
-                            }
+                            kv.call(
+                                |c| async move { c.set(cmd_id, get_key(i), get_value(i)).await },
+                            )
+                            .await
+                            .unwrap();
                         }));
                     }
                 }
@@ -81,10 +78,161 @@ fn bench_set(c: &mut Criterion) {
     });
 }
 
+/// Commit latency of set with nodes spread over three regions.
+fn bench_set_geo(c: &mut Criterion) {
+    const N: u32 = 9;
+    const NREGION: u32 = 3;
+    const NQUERIES: u32 = 100;
+    // Half of the inter-region round trip time.
+    const LATENCY: Duration = Duration::from_millis(25);
+
+    c.bench_function(
+        &format!(
+            "{} set op with {} nodes in {} regions",
+            NQUERIES, N, NREGION
+        ),
+        |b| {
+            b.iter_custom(|iters| {
+                let rt = Builder::new_multi_thread()
+                    .worker_threads(30)
+                    .enable_all()
+                    .build()
+                    .unwrap();
+
+                rt.block_on(async {
+                    let dir = tempfile::TempDir::new().unwrap();
+
+                    // Acceptor `acc-i` and KV node `kv-i` are in region `i % NREGION`.
+                    let net = Network::new();
+                    let regions: Vec<Vec<String>> = (0..NREGION)
+                        .map(|r| {
+                            (r..N)
+                                .step_by(NREGION as usize)
+                                .flat_map(|i| vec![format!("acc-{}", i), format!("kv-{}", i)])
+                                .collect()
+                        })
+                        .collect();
+                    net.links().set_regions(&regions, Link::new(LATENCY));
+
+                    let (acc_clients, acceptors, acc_net) =
+                        acceptor_cluster_on(net, dir.path(), N);
+                    let cluster_info = ClusterInfo { acc_clients };
+                    let (kv_clients, kvs, kv_net) = kv_cluster(dir.path(), N, cluster_info);
+
+                    let get_key = |i| format!("key-{}", i);
+                    let get_value = |i| format!("value-{}", i);
+
+                    let mut setter = Vec::new();
+
+                    // Warm up
+                    let c = kv_clients.first().expect("cluster should not be empty");
+                    loop {
+                        if let Ok(opt) = c.get("none".to_string()).await {
+                            assert!(opt.is_none());
+                            break;
+                        }
+                    }
+
+                    let kv = Failover::new(kv_clients).policy(Policy::Leader);
+                    let start = Instant::now();
+
+                    for _ in 0..iters {
+                        for i in 0..NQUERIES {
+                            let kv = kv.clone();
+                            setter.push(tokio::spawn(async move {
+                                let cmd_id = u64::try_from(i).unwrap();
+                                kv.call(|c| async move {
+                                    c.set(cmd_id, get_key(i), get_value(i)).await
+                                })
+                                .await
+                                .unwrap();
+                            }));
+                        }
+                    }
+
+                    for s in setter {
+                        s.await.expect("setters should not panic");
+                    }
+
+                    start.elapsed()
+                })
+            });
+        },
+    );
+}
+
+/// Latency of sequential sets with one acceptor much slower than the others.
+fn bench_set_slow_acceptor(c: &mut Criterion) {
+    const N: u32 = 5;
+    const NQUERIES: u32 = 20;
+    // Half of the round trip time to the fast acceptors.
+    const LATENCY: Duration = Duration::from_millis(1);
+    const SLOW_LATENCY: Duration = Duration::from_millis(20);
+
+    c.bench_function(
+        &format!(
+            "{} sequential set op with {} nodes, one slow acceptor",
+            NQUERIES, N
+        ),
+        |b| {
+            b.iter_custom(|iters| {
+                let rt = Builder::new_multi_thread()
+                    .worker_threads(30)
+                    .enable_all()
+                    .build()
+                    .unwrap();
+
+                rt.block_on(async {
+                    let dir = tempfile::TempDir::new().unwrap();
+
+                    let net = Network::new();
+                    net.links().set_default(Link::new(LATENCY));
+                    for i in 0..N {
+                        net.links()
+                            .connect(&format!("kv-{}", i), "acc-0", Link::new(SLOW_LATENCY));
+                    }
+
+                    let (acc_clients, acceptors, acc_net) =
+                        acceptor_cluster_on(net, dir.path(), N);
+                    let cluster_info = ClusterInfo { acc_clients };
+                    let (kv_clients, kvs, kv_net) = kv_cluster(dir.path(), N, cluster_info);
+
+                    // Warm up
+                    let c = kv_clients.first().expect("cluster should not be empty");
+                    loop {
+                        if let Ok(opt) = c.get("none".to_string()).await {
+                            assert!(opt.is_none());
+                            break;
+                        }
+                    }
+
+                    let kv = Failover::new(kv_clients).policy(Policy::Leader);
+                    let start = Instant::now();
+
+                    for iter in 0..iters {
+                        for i in 0..NQUERIES {
+                            // Sets of the same command id are skipped.
+                            let cmd_id = iter * u64::from(NQUERIES) + u64::from(i);
+                            kv.call(|c| async move {
+                                c.set(cmd_id, format!("key-{}", i), format!("value-{}", i))
+                                    .await
+                            })
+                            .await
+                            .unwrap();
+                        }
+                    }
+
+                    start.elapsed()
+                })
+            });
+        },
+    );
+}
+
 criterion_group!(
     name = benches;
     // This can be any expression that returns a `Criterion` object.
     config = Criterion::default().sample_size(10);
-    targets = bench_set,
+    targets = bench_set, bench_set_geo, bench_set_slow_acceptor,
 );
 criterion_main!(benches);
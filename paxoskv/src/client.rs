@@ -1,5 +1,7 @@
 use super::KvClient;
 
+use labrpc::anyhow::{anyhow, Result};
+
 /// Client for a KV cluster
 pub struct Client {
     clients: Vec<KvClient>,
@@ -10,4 +12,24 @@ impl Client {
     pub fn new(clients: Vec<KvClient>) -> Self {
         Self { clients }
     }
+
+    /// Discard the log entries applied by all replicas from the acceptors.
+    ///
+    /// Returns the watermark below which entries are discarded. Fails if any
+    /// replica cannot tell how far it applied.
+    pub async fn truncate(&self) -> Result<u64> {
+        let mut applied = u64::MAX;
+        for c in self.clients.iter() {
+            applied = applied.min(c.applied().await?);
+        }
+        let below = applied.saturating_add(1);
+        let mut last = anyhow!("no replica");
+        for c in self.clients.iter() {
+            match c.truncate(below).await {
+                Ok(()) => return Ok(below),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
 }
@@ -1,7 +1,7 @@
 use super::KvService;
 
 use labrpc::{
-    anyhow::Result,
+    anyhow::{bail, Result},
     random_error,
     serde::{Deserialize, Serialize},
     serde_json,
@@ -40,12 +40,25 @@ pub struct ClusterInfo {
     pub acc_clients: Vec<AcceptorClient>,
 }
 
+impl ClusterInfo {
+    /// Mark requests to acceptors as sent by node `id`.
+    pub fn with_source(&self, id: &str) -> Self {
+        Self {
+            acc_clients: self
+                .acc_clients
+                .iter()
+                .map(|c| c.clone().with_source(id))
+                .collect(),
+        }
+    }
+}
+
 impl Paxoskv {
     /// Create a new service instance by path to local DB, id and cluster information.
     ///
     /// The id should be unique between different KV services.
     pub fn new(path: impl AsRef<Path>, id: u32, cluster: ClusterInfo) -> Self {
-        let proposer = Proposer::new(id, cluster.acc_clients);
+        let proposer = Proposer::new(id, cluster.acc_clients).multi_paxos();
         let db = DB::open_default(path).unwrap();
         let term = db
             .get("term")
@@ -79,21 +92,24 @@ impl Paxoskv {
         let ent = LogEntry { id: cmd_id, op };
 
         loop {
-            self.term += 1;
+            // Only move on once the entry is applied, so that a failure, such
+            // as the key being truncated, skips no entry.
+            let term = self.term + 1;
             let cur = self
                 .proposer
-                .choose(self.term, serde_json::to_string(&ent)?)
+                .choose(term, serde_json::to_string(&ent)?)
                 .await?;
             let cur: LogEntry = serde_json::from_str(&cur)?;
             match cur.op {
                 Operation::Get { key } => {
                     assert!(key.ends_with("-"));
+                    self.db.put("term", bincode::serialize(&term)?)?;
                 }
                 Operation::Set { key, value } => {
                     assert!(key.ends_with("-"));
                     let mut batch = WriteBatch::default();
                     batch.put(&key, &value);
-                    batch.put("term", bincode::serialize(&self.term)?);
+                    batch.put("term", bincode::serialize(&term)?);
                     batch.put(&cmd_key, "done");
                     self.db.write(batch)?;
                 }
@@ -102,11 +118,12 @@ impl Paxoskv {
 
                     let mut batch = WriteBatch::default();
                     batch.delete(&key);
-                    batch.put("term", bincode::serialize(&self.term)?);
+                    batch.put("term", bincode::serialize(&term)?);
                     batch.put(&cmd_key, "done");
                     self.db.write(batch)?;
                 }
             }
+            self.term = term;
             if cur.id == ent.id {
                 break Ok(());
             }
@@ -147,6 +164,19 @@ impl KvService for Paxoskv {
         let op = Operation::Remove { key };
         self.append(cmd_id, op).await
     }
+    async fn applied(&mut self) -> Result<u64> {
+        Ok(self
+            .db
+            .get("term")?
+            .map_or(Ok(0), |v| bincode::deserialize(&v))?)
+    }
+    async fn truncate(&mut self, below: u64) -> Result<()> {
+        // This replica would lose the entries it has not applied yet.
+        if below > self.term + 1 {
+            bail!("truncate below {} past applied {}", below, self.term);
+        }
+        self.proposer.truncate(below).await
+    }
 }
 
 #[cfg(test)]
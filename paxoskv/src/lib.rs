@@ -8,6 +8,13 @@ labrpc::service! {
         fn get(key: String) -> Option<String>;
         fn set(cmd_id: u64, key: String, value: String) -> ();
         fn remove(cmd_id: u64, key: String) -> ();
+        fn applied() -> u64;
+        /// Discard the log entries below `below` from the acceptors.
+        ///
+        /// `below` must be at most one past the entries applied by every
+        /// replica, as computed by [`Client::truncate`](crate::client::Client::truncate).
+        /// A replica refuses a `below` past its own applied entries.
+        fn truncate(below: u64) -> ();
     }
 }
 
@@ -4,20 +4,34 @@ use paxos::{Acceptor, AcceptorClient, AcceptorServer, Proposer, ProposerService}
 
 use labrpc::server::Server;
 use labrpc::*;
-use tokio::task::JoinHandle;
+use tokio::{task::JoinHandle, time::Instant};
 
 use crate::kv::Paxoskv;
-use paxos::tests::acceptor_cluster;
-use std::{convert::TryFrom, path::Path};
+use labrpc::{
+    failover::{Failover, Policy},
+    nemesis::{self, Nemesis, SeedGuard},
+};
+use paxos::tests::{acceptor_cluster, acceptor_cluster_on, chaos_duration};
+use std::{convert::TryFrom, path::Path, time::Duration};
 
 /// Create a cluter of KV store for testing.
+///
+/// Node `i` is named `kv-<i>`, which is also the source of its requests to acceptors.
 pub fn kv_cluster(
     dir: &Path,
     n: u32,
     cluster_info: ClusterInfo,
 ) -> (Vec<KvClient>, Vec<JoinHandle<()>>, JoinHandle<()>) {
-    let mut net = Network::new();
+    kv_cluster_on(Network::new(), dir, n, cluster_info)
+}
 
+/// Create a cluster of KV store on a given network, see [`kv_cluster`].
+pub fn kv_cluster_on(
+    mut net: Network,
+    dir: &Path,
+    n: u32,
+    cluster_info: ClusterInfo,
+) -> (Vec<KvClient>, Vec<JoinHandle<()>>, JoinHandle<()>) {
     let mut clients = Vec::new();
 
     let mut servers = Vec::new();
@@ -29,7 +43,7 @@ pub fn kv_cluster(
         let id = server_id(i);
 
         let p = dir.join(&id);
-        let cluster_info = cluster_info.clone();
+        let cluster_info = cluster_info.with_source(&id);
         let (client, server_routine) = net
             .register_service::<KvServer<Paxoskv>, _, _, _>(id, move || {
                 Paxoskv::new(p.clone(), i, cluster_info.clone())
@@ -37,16 +51,6 @@ pub fn kv_cluster(
         clients.push(client);
         servers.push(tokio::spawn(server_routine));
     }
-    // Wait until servers finish registration.
-    let nodes = net.nodes.clone();
-    for i in 0..n {
-        let id = server_id(i);
-        loop {
-            if nodes.clone().lock().unwrap().get(&id).is_some() {
-                break;
-            }
-        }
-    }
     let net_thread = tokio::spawn(async move {
         net.run().await;
     });
@@ -58,6 +62,7 @@ async fn test_set_and_get() {
     const N: u32 = 10;
 
     env_logger::init();
+    let _trace = labrpc::trace::init_from_env();
     let dir = tempfile::TempDir::new().unwrap();
 
     let (acc_clients, acceptors, acc_net) = acceptor_cluster(dir.path(), N);
@@ -67,23 +72,16 @@ async fn test_set_and_get() {
     let get_key = |i| format!("key-{}", i);
     let get_value = |i| format!("value-{}", i);
 
+    let kv = Failover::new(kv_clients).policy(Policy::Leader);
+
     let mut setter = Vec::new();
     for i in 0..N {
-        let clients = kv_clients.clone();
+        let kv = kv.clone();
         setter.push(tokio::spawn(async move {
             let cmd_id = u64::try_from(i).unwrap();
-            loop {
-                let mut finish = false;
-                for c in clients.iter() {
-                    if c.set(cmd_id, get_key(i), get_value(i)).await.is_ok() {
-                        finish = true;
-                        break;
-                    }
-                }
-                if finish {
-                    break;
-                }
-            }
+            kv.call(|c| async move { c.set(cmd_id, get_key(i), get_value(i)).await })
+                .await
+                .unwrap();
         }));
     }
 
@@ -92,20 +90,196 @@ async fn test_set_and_get() {
     }
 
     for i in 0..N {
-        let cmd_id = u64::try_from(i).unwrap();
-        loop {
-            let mut finish = false;
-            for c in kv_clients.iter() {
-                if let Ok(opt) = c.get(get_key(i)).await {
-                    let v = opt.expect("expect some value saved before");
-                    assert!(v == get_value(i));
-                    finish = true;
-                    break;
-                }
-            }
-            if finish {
-                break;
+        let opt = kv
+            .call(|c| async move { c.get(get_key(i)).await })
+            .await
+            .unwrap();
+        let v = opt.expect("expect some value saved before");
+        assert!(v == get_value(i));
+    }
+}
+
+/// Keys set before truncating the log stay readable, and later operations
+/// use the keys above the watermark.
+#[tokio::test(flavor = "multi_thread", worker_threads = 20)]
+async fn test_truncate() {
+    const N: u32 = 3;
+    const NKEY: u32 = 10;
+
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let (acc_clients, _acceptors, _acc_net) = acceptor_cluster(dir.path(), N);
+    let cluster_info = ClusterInfo { acc_clients };
+    let (kv_clients, _kvs, _kv_net) = kv_cluster(dir.path(), N, cluster_info);
+
+    let get_key = |i| format!("key-{}", i);
+    let get_value = |i| format!("value-{}", i);
+
+    let kv = Failover::new(kv_clients.clone()).policy(Policy::Leader);
+    let set = |i: u32| {
+        let kv = kv.clone();
+        async move {
+            let cmd_id = u64::from(i);
+            kv.call(|c| async move { c.set(cmd_id, get_key(i), get_value(i)).await })
+                .await
+                .unwrap();
+        }
+    };
+    for i in 0..NKEY {
+        set(i).await;
+    }
+    // Let every replica apply the log.
+    for c in kv_clients.iter() {
+        c.get(get_key(0)).await.unwrap();
+    }
+
+    let below = crate::client::Client::new(kv_clients)
+        .truncate()
+        .await
+        .unwrap();
+    assert!(below > u64::from(NKEY), "{}", below);
+
+    set(NKEY).await;
+    for i in 0..=NKEY {
+        let opt = kv
+            .call(|c| async move { c.get(get_key(i)).await })
+            .await
+            .unwrap();
+        assert_eq!(opt, Some(get_value(i)), "key {}", i);
+    }
+}
+
+/// A replica lagging behind the watermark fails to append rather than skip
+/// the entries it missed.
+#[tokio::test(flavor = "multi_thread", worker_threads = 20)]
+async fn test_truncate_lagging() {
+    const N: u32 = 3;
+    const NKEY: u32 = 5;
+
+    let _ = env_logger::try_init();
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let (acc_clients, _acceptors, _acc_net) = acceptor_cluster(dir.path(), N);
+    let cluster_info = ClusterInfo { acc_clients };
+    let (kv_clients, _kvs, _kv_net) = kv_cluster(dir.path(), N, cluster_info);
+    let (leader, lagging) = (&kv_clients[0], &kv_clients[1]);
+
+    for i in 0..NKEY {
+        leader
+            .set(u64::from(i), format!("key-{}", i), "value".to_string())
+            .await
+            .unwrap();
+    }
+    let below = leader.applied().await.unwrap() + 1;
+    // The lagging replica refuses to discard entries it has not applied.
+    assert!(lagging.truncate(below).await.is_err());
+    leader.truncate(below).await.unwrap();
+
+    for i in 0..below + 1 {
+        let cmd_id = u64::from(NKEY) + i;
+        let set = lagging.set(cmd_id, "key".to_string(), "value".to_string());
+        assert!(set.await.is_err());
+        // The failure crashes the replica, which restarts from its database.
+        let applied = loop {
+            match lagging.applied().await {
+                Ok(applied) => break applied,
+                Err(_) => tokio::time::sleep(Duration::from_millis(1)).await,
             }
+        };
+        assert_eq!(applied, 0);
+    }
+}
+
+/// Set and get keys while KV nodes and acceptors crash, restart, get
+/// partitioned and lose messages.
+///
+/// Runs for [`chaos_duration`], thus ignored by default.
+#[tokio::test(flavor = "multi_thread", worker_threads = 20)]
+#[ignore]
+async fn test_chaos() {
+    const N: u32 = 5;
+    const NSETTER: u32 = 5;
+
+    let _ = env_logger::try_init();
+    let seed = nemesis::seed_from_env();
+    let _seed = SeedGuard(seed);
+    let dir = tempfile::TempDir::new().unwrap();
+
+    let acc_net = Network::new();
+    let acc_faults = acc_net.faults();
+    let (acc_clients, _acceptors, _acc_net) = acceptor_cluster_on(acc_net, dir.path(), N);
+    let cluster_info = ClusterInfo { acc_clients };
+    let kv_net = Network::new();
+    let kv_faults = kv_net.faults();
+    let (kv_clients, _kvs, _kv_net) = kv_cluster_on(kv_net, dir.path(), N, cluster_info);
+
+    let duration = chaos_duration();
+    let config = nemesis::Config {
+        max_crashed: (N as usize - 1) / 2,
+        ..nemesis::Config::default()
+    };
+    let mut nemesis = Nemesis::new(seed, config)
+        .target(
+            acc_faults.clone(),
+            (0..N).map(|i| format!("acc-{}", i)).collect(),
+        )
+        .target(
+            kv_faults.clone(),
+            (0..N).map(|i| format!("kv-{}", i)).collect(),
+        );
+    let chaos = tokio::spawn(async move { nemesis.run(duration).await });
+
+    let get_key = |i| format!("key-{}", i);
+    let get_value = |i| format!("value-{}", i);
+
+    let kv = Failover::new(kv_clients)
+        .policy(Policy::Sticky)
+        .backoff(Duration::from_millis(10));
+
+    // Setter `s` sets keys `s`, `s + NSETTER`, ... until the chaos ends, by
+    // the clock of tokio like the nemesis, counting the sets issued with a
+    // node down.
+    let start = Instant::now();
+    let setters: Vec<_> = (0..NSETTER)
+        .map(|s| {
+            let kv = kv.clone();
+            let faults = [acc_faults.clone(), kv_faults.clone()];
+            tokio::spawn(async move {
+                let mut i = s;
+                let mut faulty = 0;
+                while start.elapsed() < duration {
+                    if faults.iter().any(|f| !f.crashed().is_empty()) {
+                        faulty += 1;
+                    }
+                    let cmd_id = u64::from(i);
+                    kv.call(|c| async move { c.set(cmd_id, get_key(i), get_value(i)).await })
+                        .await
+                        .unwrap();
+                    i += NSETTER;
+                }
+                (i, faulty)
+            })
+        })
+        .collect();
+    let mut ends = Vec::new();
+    let mut faulty = 0;
+    for s in setters {
+        let (end, n) = s.await.expect("setters should not panic");
+        ends.push(end);
+        faulty += n;
+    }
+    chaos.await.unwrap();
+    // The nemesis acted while keys were set, not only before.
+    assert!(faulty > 0, "no key set with a node down");
+
+    for (s, end) in (0..NSETTER).zip(ends) {
+        for i in (s..end).step_by(NSETTER as usize) {
+            let opt = kv
+                .call(|c| async move { c.get(get_key(i)).await })
+                .await
+                .unwrap();
+            assert_eq!(opt, Some(get_value(i)), "key {}", i);
         }
     }
 }